@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS user_sessions_family_id_idx;
+DROP INDEX IF EXISTS user_sessions_refresh_token_hash_idx;
+
+ALTER TABLE user_sessions
+    DROP COLUMN rotated_at,
+    DROP COLUMN refresh_token_hash,
+    DROP COLUMN family_id;
@@ -0,0 +1,12 @@
+ALTER TABLE user_sessions
+    ADD COLUMN family_id UUID,
+    ADD COLUMN refresh_token_hash TEXT,
+    ADD COLUMN rotated_at TIMESTAMP;
+
+-- 已有的会话各自成为一个独立的会话族
+UPDATE user_sessions SET family_id = session_id;
+
+ALTER TABLE user_sessions ALTER COLUMN family_id SET NOT NULL;
+
+CREATE UNIQUE INDEX user_sessions_refresh_token_hash_idx ON user_sessions (refresh_token_hash);
+CREATE INDEX user_sessions_family_id_idx ON user_sessions (family_id);
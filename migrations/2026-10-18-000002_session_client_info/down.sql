@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS user_sessions_user_id_idx;
+
+ALTER TABLE user_sessions
+    DROP COLUMN last_seen_at,
+    DROP COLUMN user_agent,
+    DROP COLUMN ip_address,
+    DROP COLUMN device_name;
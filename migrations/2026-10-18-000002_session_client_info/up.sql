@@ -0,0 +1,9 @@
+ALTER TABLE user_sessions
+    ADD COLUMN device_name VARCHAR(100),
+    ADD COLUMN ip_address VARCHAR(45),
+    ADD COLUMN user_agent TEXT,
+    ADD COLUMN last_seen_at TIMESTAMP;
+
+UPDATE user_sessions SET last_seen_at = created_at;
+
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
@@ -0,0 +1,7 @@
+-- throttle_key 形如 `user:<用户名>` 或 `ip:<地址>`，不存在的用户名同样计数
+CREATE TABLE login_throttles (
+    throttle_key VARCHAR(200) PRIMARY KEY,
+    failed_count INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMP,
+    locked_until TIMESTAMP
+);
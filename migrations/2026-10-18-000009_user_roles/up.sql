@@ -0,0 +1,4 @@
+-- 第一个管理员需要手动设置：UPDATE users SET role = 'admin' WHERE username = '...';
+ALTER TABLE users
+    ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'student' CHECK (role IN ('admin', 'teacher', 'student')),
+    ADD COLUMN disabled_at TIMESTAMP;
@@ -0,0 +1,4 @@
+ALTER TABLE chats DROP COLUMN classroom_id;
+
+DROP TABLE classroom_members;
+DROP TABLE classrooms;
@@ -0,0 +1,30 @@
+-- system_prompt 由教师设置，学生在班级内创建的对话发送给模型时附带
+CREATE TABLE classrooms (
+    classroom_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    teacher_id UUID NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    invite_code VARCHAR(16) UNIQUE NOT NULL,
+    system_prompt TEXT,
+    created_at TIMESTAMP DEFAULT NOW(),
+    FOREIGN KEY (teacher_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX classrooms_teacher_id_idx ON classrooms (teacher_id);
+
+-- share_chats 为学生的授权：为 TRUE 时教师才能查看其对话标题和活跃情况
+CREATE TABLE classroom_members (
+    classroom_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    share_chats BOOLEAN NOT NULL DEFAULT FALSE,
+    joined_at TIMESTAMP DEFAULT NOW(),
+    PRIMARY KEY (classroom_id, user_id),
+    FOREIGN KEY (classroom_id) REFERENCES classrooms(classroom_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX classroom_members_user_id_idx ON classroom_members (user_id);
+
+ALTER TABLE chats
+    ADD COLUMN classroom_id UUID REFERENCES classrooms(classroom_id) ON DELETE SET NULL;
+
+CREATE INDEX chats_classroom_id_idx ON chats (classroom_id);
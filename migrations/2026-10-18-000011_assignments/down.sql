@@ -0,0 +1,2 @@
+DROP TABLE assignment_submissions;
+DROP TABLE assignments;
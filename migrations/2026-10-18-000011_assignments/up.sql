@@ -0,0 +1,29 @@
+-- problem_latex 为教师上传题目图片后 OCR 识别出的内容，原图不保存
+CREATE TABLE assignments (
+    assignment_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    classroom_id UUID NOT NULL,
+    title VARCHAR(100) NOT NULL,
+    problems TEXT NOT NULL,
+    problem_latex TEXT,
+    due_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT NOW(),
+    FOREIGN KEY (classroom_id) REFERENCES classrooms(classroom_id) ON DELETE CASCADE
+);
+
+CREATE INDEX assignments_classroom_id_idx ON assignments (classroom_id);
+
+-- 每个学生对每份作业只有一条提交记录，对应开始作业时创建的对话
+CREATE TABLE assignment_submissions (
+    assignment_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    chat_id UUID UNIQUE NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'in_progress' CHECK (status IN ('in_progress', 'submitted', 'reviewed')),
+    started_at TIMESTAMP DEFAULT NOW(),
+    submitted_at TIMESTAMP,
+    reviewed_at TIMESTAMP,
+    feedback TEXT,
+    PRIMARY KEY (assignment_id, user_id),
+    FOREIGN KEY (assignment_id) REFERENCES assignments(assignment_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (chat_id) REFERENCES chats(chat_id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+DROP TABLE ocr_records;
+
+ALTER TABLE users DROP COLUMN deletion_scheduled_at;
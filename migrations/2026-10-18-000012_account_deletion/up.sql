@@ -0,0 +1,15 @@
+-- 申请注销后记录计划删除的时间，宽限期内可以恢复，到期后由后台任务彻底删除
+ALTER TABLE users ADD COLUMN deletion_scheduled_at TIMESTAMP;
+
+CREATE INDEX users_deletion_scheduled_at_idx ON users (deletion_scheduled_at) WHERE deletion_scheduled_at IS NOT NULL;
+
+-- 只保存识别结果，不保存原图
+CREATE TABLE ocr_records (
+    record_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX ocr_records_user_id_idx ON ocr_records (user_id);
@@ -0,0 +1,5 @@
+DROP INDEX chats_user_last_message_idx;
+DROP INDEX chats_user_created_idx;
+
+ALTER TABLE chats DROP COLUMN last_message_at;
+ALTER TABLE chats ALTER COLUMN created_at DROP NOT NULL;
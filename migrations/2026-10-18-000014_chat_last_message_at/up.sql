@@ -0,0 +1,18 @@
+-- 游标分页依赖排序列非空
+UPDATE chats SET created_at = NOW() WHERE created_at IS NULL;
+ALTER TABLE chats ALTER COLUMN created_at SET NOT NULL;
+
+-- last_message_at 在写入消息时同步更新，没有消息的对话取创建时间
+ALTER TABLE chats ADD COLUMN last_message_at TIMESTAMP;
+
+UPDATE chats SET last_message_at = COALESCE(
+    (SELECT MAX(messages.timestamp) FROM messages WHERE messages.chat_id = chats.chat_id),
+    chats.created_at
+);
+
+ALTER TABLE chats
+    ALTER COLUMN last_message_at SET NOT NULL,
+    ALTER COLUMN last_message_at SET DEFAULT NOW();
+
+CREATE INDEX chats_user_created_idx ON chats (user_id, created_at DESC, chat_id DESC);
+CREATE INDEX chats_user_last_message_idx ON chats (user_id, last_message_at DESC, chat_id DESC);
@@ -0,0 +1,4 @@
+ALTER TABLE chats DROP COLUMN last_message_seq;
+
+ALTER TABLE messages DROP CONSTRAINT messages_chat_seq_key;
+ALTER TABLE messages DROP COLUMN seq;
@@ -0,0 +1,20 @@
+-- 消息在对话内的序号，从 1 开始连续递增
+ALTER TABLE messages ADD COLUMN seq BIGINT;
+
+UPDATE messages SET seq = numbered.seq
+FROM (
+    SELECT message_id, ROW_NUMBER() OVER (PARTITION BY chat_id ORDER BY timestamp, message_id) AS seq
+    FROM messages
+) AS numbered
+WHERE messages.message_id = numbered.message_id;
+
+ALTER TABLE messages ALTER COLUMN seq SET NOT NULL;
+ALTER TABLE messages ADD CONSTRAINT messages_chat_seq_key UNIQUE (chat_id, seq);
+
+-- 对话已分配的最大序号，写消息时原子递增，同时作为行锁保证并发写入的顺序
+ALTER TABLE chats ADD COLUMN last_message_seq BIGINT NOT NULL DEFAULT 0;
+
+UPDATE chats SET last_message_seq = COALESCE(
+    (SELECT MAX(messages.seq) FROM messages WHERE messages.chat_id = chats.chat_id),
+    0
+);
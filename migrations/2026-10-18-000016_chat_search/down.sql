@@ -0,0 +1,10 @@
+DROP INDEX chats_search_idx;
+DROP INDEX messages_search_idx;
+
+ALTER TABLE chats DROP COLUMN search_vector;
+ALTER TABLE messages DROP COLUMN search_vector;
+
+DROP FUNCTION chat_search_headline(TEXT, TSQUERY);
+DROP FUNCTION chat_search_query(TEXT);
+DROP FUNCTION chat_search_vector(TEXT);
+DROP FUNCTION chat_search_normalize(TEXT);
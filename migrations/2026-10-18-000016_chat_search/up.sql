@@ -0,0 +1,53 @@
+-- 全文检索的分词预处理。内置解析器会把连续的汉字当作一个词，这里在每个中日文字符和全角标点
+-- 两侧插入不可见的分隔符 \x1f，按单字切分，查询时再用短语查询要求单字相邻，效果接近按原文子串匹配。
+-- LaTeX 的 \、{}、^、_ 本身就是分隔符，\int_0^1 x^2 dx 会切成 int 0 1 x 2 dx，无需额外处理
+CREATE FUNCTION chat_search_normalize(content TEXT) RETURNS TEXT
+LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT regexp_replace(content, '([　-〿぀-ヿ㐀-䶿一-鿿豈-﫿＀-￯])', E'\x1f\\1\x1f', 'g')
+$$;
+
+CREATE FUNCTION chat_search_vector(content TEXT) RETURNS TSVECTOR
+LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT to_tsvector('simple', chat_search_normalize(content))
+$$;
+
+-- 按空白拆分关键词，每个关键词作为短语，关键词之间取交集
+CREATE FUNCTION chat_search_query(keywords TEXT) RETURNS TSQUERY
+LANGUAGE plpgsql IMMUTABLE PARALLEL SAFE AS $$
+DECLARE
+    term TEXT;
+    result TSQUERY;
+BEGIN
+    FOR term IN SELECT t FROM regexp_split_to_table(keywords, '\s+') AS t WHERE t <> '' LOOP
+        IF result IS NULL THEN
+            result := phraseto_tsquery('simple', chat_search_normalize(term));
+        ELSE
+            result := result && phraseto_tsquery('simple', chat_search_normalize(term));
+        END IF;
+    END LOOP;
+    RETURN result;
+END;
+$$;
+
+-- 生成高亮摘要：去掉预处理插入的分隔符，并把相邻单字的高亮合并成一段
+CREATE FUNCTION chat_search_headline(content TEXT, query TSQUERY) RETURNS TEXT
+LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT replace(replace(
+        ts_headline(
+            'simple',
+            chat_search_normalize(content),
+            query,
+            'StartSel=<mark>, StopSel=</mark>, MaxWords=35, MinWords=15'
+        ),
+        E'\x1f', ''), '</mark><mark>', '')
+$$;
+
+-- 检索列由数据库维护，不出现在 schema.rs 中，只在检索的原生 SQL 里使用
+ALTER TABLE messages ADD COLUMN search_vector TSVECTOR
+    GENERATED ALWAYS AS (chat_search_vector(content)) STORED;
+
+ALTER TABLE chats ADD COLUMN search_vector TSVECTOR
+    GENERATED ALWAYS AS (chat_search_vector(title)) STORED;
+
+CREATE INDEX messages_search_idx ON messages USING GIN (search_vector);
+CREATE INDEX chats_search_idx ON chats USING GIN (search_vector);
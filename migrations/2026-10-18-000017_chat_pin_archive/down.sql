@@ -0,0 +1,9 @@
+DROP INDEX chats_user_created_idx;
+DROP INDEX chats_user_last_message_idx;
+
+CREATE INDEX chats_user_created_idx ON chats (user_id, created_at DESC, chat_id DESC);
+CREATE INDEX chats_user_last_message_idx ON chats (user_id, last_message_at DESC, chat_id DESC);
+
+ALTER TABLE chats
+    DROP COLUMN pinned,
+    DROP COLUMN archived;
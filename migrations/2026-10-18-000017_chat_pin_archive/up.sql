@@ -0,0 +1,10 @@
+ALTER TABLE chats
+    ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- 对话列表按置顶优先排序，并默认排除已归档的对话，分页索引随之调整
+DROP INDEX chats_user_created_idx;
+DROP INDEX chats_user_last_message_idx;
+
+CREATE INDEX chats_user_created_idx ON chats (user_id, archived, pinned DESC, created_at DESC, chat_id DESC);
+CREATE INDEX chats_user_last_message_idx ON chats (user_id, archived, pinned DESC, last_message_at DESC, chat_id DESC);
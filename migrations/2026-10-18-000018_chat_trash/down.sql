@@ -0,0 +1,8 @@
+DROP INDEX chats_user_created_idx;
+DROP INDEX chats_user_last_message_idx;
+
+CREATE INDEX chats_user_created_idx ON chats (user_id, archived, pinned DESC, created_at DESC, chat_id DESC);
+CREATE INDEX chats_user_last_message_idx ON chats (user_id, archived, pinned DESC, last_message_at DESC, chat_id DESC);
+
+DROP INDEX chats_user_deleted_idx;
+ALTER TABLE chats DROP COLUMN deleted_at;
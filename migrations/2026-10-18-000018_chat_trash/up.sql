@@ -0,0 +1,11 @@
+-- 删除的对话先进入回收站，超过保留期后由后台任务彻底删除
+ALTER TABLE chats ADD COLUMN deleted_at TIMESTAMP;
+
+CREATE INDEX chats_user_deleted_idx ON chats (user_id, deleted_at DESC) WHERE deleted_at IS NOT NULL;
+
+-- 对话列表只查询未删除的对话
+DROP INDEX chats_user_created_idx;
+DROP INDEX chats_user_last_message_idx;
+
+CREATE INDEX chats_user_created_idx ON chats (user_id, archived, pinned DESC, created_at DESC, chat_id DESC) WHERE deleted_at IS NULL;
+CREATE INDEX chats_user_last_message_idx ON chats (user_id, archived, pinned DESC, last_message_at DESC, chat_id DESC) WHERE deleted_at IS NULL;
@@ -0,0 +1,6 @@
+DROP TABLE chat_tags;
+DROP TABLE tags;
+
+ALTER TABLE chats DROP COLUMN folder_id;
+
+DROP TABLE folders;
@@ -0,0 +1,38 @@
+-- 文件夹可以嵌套，parent_id 为空表示顶层。删除文件夹时子文件夹一并删除，其中的对话回到顶层
+CREATE TABLE folders (
+    folder_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    parent_id UUID,
+    name VARCHAR(100) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (parent_id) REFERENCES folders(folder_id) ON DELETE CASCADE
+);
+
+CREATE INDEX folders_user_id_idx ON folders (user_id);
+CREATE INDEX folders_parent_id_idx ON folders (parent_id);
+
+ALTER TABLE chats
+    ADD COLUMN folder_id UUID REFERENCES folders(folder_id) ON DELETE SET NULL;
+
+CREATE INDEX chats_folder_id_idx ON chats (folder_id);
+
+-- 标签按名称在用户内唯一，给对话打标签时不存在的标签会自动创建
+CREATE TABLE tags (
+    tag_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    name VARCHAR(50) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, name),
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+);
+
+CREATE TABLE chat_tags (
+    chat_id UUID NOT NULL,
+    tag_id UUID NOT NULL,
+    PRIMARY KEY (chat_id, tag_id),
+    FOREIGN KEY (chat_id) REFERENCES chats(chat_id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tags(tag_id) ON DELETE CASCADE
+);
+
+CREATE INDEX chat_tags_tag_id_idx ON chat_tags (tag_id);
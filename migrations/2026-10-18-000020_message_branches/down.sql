@@ -0,0 +1,4 @@
+ALTER TABLE chats DROP COLUMN active_message_id;
+
+DROP INDEX messages_parent_message_id_idx;
+ALTER TABLE messages DROP COLUMN parent_message_id;
@@ -0,0 +1,18 @@
+-- 消息组成一棵树：编辑用户消息时在同一父消息下新建兄弟分支，原分支保留
+ALTER TABLE messages
+    ADD COLUMN parent_message_id UUID REFERENCES messages(message_id) ON DELETE CASCADE;
+
+-- 已有对话是一条链，按序号串起来
+UPDATE messages SET parent_message_id = prev.message_id
+FROM messages AS prev
+WHERE prev.chat_id = messages.chat_id AND prev.seq = messages.seq - 1;
+
+CREATE INDEX messages_parent_message_id_idx ON messages (parent_message_id);
+
+-- 当前分支的最后一条消息，新消息默认接在它后面，查看对话时从它向上回溯
+ALTER TABLE chats
+    ADD COLUMN active_message_id UUID REFERENCES messages(message_id) ON DELETE SET NULL;
+
+UPDATE chats SET active_message_id = messages.message_id
+FROM messages
+WHERE messages.chat_id = chats.chat_id AND messages.seq = chats.last_message_seq;
@@ -0,0 +1,3 @@
+DROP INDEX chats_forked_from_chat_id_idx;
+ALTER TABLE chats DROP COLUMN forked_from_message_id;
+ALTER TABLE chats DROP COLUMN forked_from_chat_id;
@@ -0,0 +1,6 @@
+-- 从其他对话的某条消息分叉出来的对话，记录来源。来源被删除后置空，分叉出的对话保留
+ALTER TABLE chats
+    ADD COLUMN forked_from_chat_id UUID REFERENCES chats(chat_id) ON DELETE SET NULL,
+    ADD COLUMN forked_from_message_id UUID REFERENCES messages(message_id) ON DELETE SET NULL;
+
+CREATE INDEX chats_forked_from_chat_id_idx ON chats (forked_from_chat_id);
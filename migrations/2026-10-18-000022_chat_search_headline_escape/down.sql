@@ -0,0 +1,11 @@
+CREATE OR REPLACE FUNCTION chat_search_headline(content TEXT, query TSQUERY) RETURNS TEXT
+LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT replace(replace(
+        ts_headline(
+            'simple',
+            chat_search_normalize(content),
+            query,
+            'StartSel=<mark>, StopSel=</mark>, MaxWords=35, MinWords=15'
+        ),
+        E'\x1f', ''), '</mark><mark>', '')
+$$;
@@ -0,0 +1,16 @@
+-- 摘要先对原文做 HTML 转义再加高亮，返回值中只有服务端插入的 <mark> 是标签，可以直接按 HTML 展示。
+-- 转义得到的实体在解析器中是单独的不计入检索的词元，不影响匹配，也不会被摘要截断
+CREATE OR REPLACE FUNCTION chat_search_headline(content TEXT, query TSQUERY) RETURNS TEXT
+LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT replace(replace(
+        ts_headline(
+            'simple',
+            chat_search_normalize(
+                replace(replace(replace(replace(replace(content,
+                    '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;')
+            ),
+            query,
+            'StartSel=<mark>, StopSel=</mark>, MaxWords=35, MinWords=15'
+        ),
+        E'\x1f', ''), '</mark><mark>', '')
+$$;
@@ -0,0 +1,5 @@
+-- 已用于完成两步登录的挑战令牌 jti，保留到令牌过期，防止同一挑战令牌在有效期内被重复使用
+CREATE TABLE used_mfa_challenges (
+    jti UUID PRIMARY KEY,
+    expires_at TIMESTAMP NOT NULL
+);
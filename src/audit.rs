@@ -0,0 +1,59 @@
+use actix_web::HttpRequest;
+use serde_json::Value;
+use uuid::Uuid;
+use crate::database::{add_audit_event, DbPool};
+use crate::handlers::client_info;
+use crate::models::AuditEvent;
+
+/// 审计事件类型，按 `领域.动作` 命名，查询时可以用 `auth.*` 按前缀筛选
+pub const LOGIN_SUCCEEDED: &str = "auth.login_succeeded";
+pub const LOGIN_FAILED: &str = "auth.login_failed";
+pub const LOGIN_THROTTLED: &str = "auth.login_throttled";
+pub const MFA_FAILED: &str = "auth.mfa_failed";
+pub const LOGOUT: &str = "auth.logout";
+pub const REFRESH_REUSED: &str = "auth.refresh_token_reused";
+pub const SESSION_REVOKED: &str = "auth.session_revoked";
+pub const SESSIONS_REVOKED: &str = "auth.sessions_revoked";
+pub const TOKEN_REJECTED: &str = "auth.token_rejected";
+pub const API_KEY_REJECTED: &str = "auth.api_key_rejected";
+pub const PASSWORD_CHANGED: &str = "auth.password_changed";
+pub const PASSWORD_RESET_REQUESTED: &str = "auth.password_reset_requested";
+pub const PASSWORD_RESET: &str = "auth.password_reset";
+pub const TOTP_ENABLED: &str = "auth.totp_enabled";
+pub const TOTP_DISABLED: &str = "auth.totp_disabled";
+pub const RECOVERY_CODES_REGENERATED: &str = "auth.recovery_codes_regenerated";
+pub const API_KEY_CREATED: &str = "auth.api_key_created";
+pub const API_KEY_REVOKED: &str = "auth.api_key_revoked";
+pub const ACCOUNT_EXPORTED: &str = "account.exported";
+pub const ACCOUNT_DELETION_REQUESTED: &str = "account.deletion_requested";
+pub const ACCOUNT_RESTORED: &str = "account.restored";
+pub const CHAT_TRASHED: &str = "chat.trashed";
+pub const CHAT_RESTORED: &str = "chat.restored";
+pub const CHAT_DELETED: &str = "chat.deleted";
+pub const ADMIN_USER_DISABLED: &str = "admin.user_disabled";
+pub const ADMIN_USER_ENABLED: &str = "admin.user_enabled";
+pub const ADMIN_ROLE_CHANGED: &str = "admin.role_changed";
+pub const ADMIN_PASSWORD_RESET: &str = "admin.password_reset";
+pub const ADMIN_USER_UNLOCKED: &str = "admin.user_unlocked";
+
+/// 写入一条审计事件，IP 和 User-Agent 取自请求。写入失败只记录日志，不影响请求本身
+pub fn record(
+    pool: &DbPool,
+    req: &HttpRequest,
+    event_type: &str,
+    actor_id: Option<Uuid>,
+    target: Option<(&str, &str)>,
+    details: Value,
+) {
+    let mut event = AuditEvent::new(event_type, actor_id)
+        .with_client_info(&client_info(req, None))
+        .with_details(details);
+
+    if let Some((target_type, target_id)) = target {
+        event = event.with_target(target_type, target_id);
+    }
+
+    if let Err(err) = add_audit_event(pool, &event) {
+        println!("审计日志写入失败: {}", err);
+    }
+}
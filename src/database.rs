@@ -1,21 +1,41 @@
-use crate::models::{NewChat, NewSession, NewUser, NewMessage, User, Chat, Session, Message};
+use crate::models::{NewChat, NewSession, NewUser, NewMessage, NewPasswordResetToken, NewEmailVerificationToken, NewRecoveryCode, LoginThrottle, OidcLoginState, NewUserIdentity, ApiKey, UsageStats, Classroom, ClassroomChangeset, ClassroomMember, MemberActivity, Assignment, AssignmentSubmission, ChatActivity, ChatSort, ChatCursor, ChatListFilter, ChatChangeset, Folder, FolderChangeset, Tag, ChatTag, BranchMessage, SearchHit, OcrRecord, UserIdentity, AuditEvent, User, Chat, Session, Message};
 use crate::schema::chats;
 use crate::schema::users;
 use crate::schema::messages;
 use crate::schema::user_sessions;
+use crate::schema::password_reset_tokens;
+use crate::schema::email_verification_tokens;
+use crate::schema::user_recovery_codes;
+use crate::schema::login_throttles;
+use crate::schema::used_mfa_challenges;
+use crate::schema::oidc_login_states;
+use crate::schema::user_identities;
+use crate::schema::api_keys;
+use crate::schema::classrooms;
+use crate::schema::classroom_members;
+use crate::schema::assignments;
+use crate::schema::assignment_submissions;
+use crate::schema::ocr_records;
+use crate::schema::audit_events;
+use crate::schema::folders;
+use crate::schema::tags;
+use crate::schema::chat_tags;
 
 
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
+use diesel::pg::{Pg, PgConnection};
 use diesel::r2d2::{ConnectionManager, Pool};
 use std::env;
 use dotenv::dotenv;
 use uuid::Uuid;
 use chrono::NaiveDateTime;
+use crate::utils::{now, SUBMISSION_IN_PROGRESS, SUBMISSION_REVIEWED};
 
 
 pub type DbPool = Pool<ConnectionManager<PgConnection>>;
 
+define_sql_function!(fn lower(x: diesel::sql_types::Varchar) -> diesel::sql_types::Varchar);
+
 pub fn init_pool() -> DbPool {
     dotenv().ok();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -35,105 +55,2011 @@ pub fn get_user_by_username(pool: &DbPool, user_name: &String) -> Result<User, d
     ))?;
     
     users::table.filter(users::username.eq(user_name))
-        .first::<User>(&mut conn)
+        .select(User::as_select())
+        .first(&mut conn)
 }
 
-pub fn add_new_user(pool: &DbPool, new_user: &NewUser) -> Result<usize, diesel::result::Error>{
+pub fn get_user_by_id(pool: &DbPool, userid: Uuid) -> Result<User, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
-    
-    diesel::insert_into(users::table)
-        .values(new_user)
+
+    users::table.filter(users::user_id.eq(userid))
+        .select(User::as_select())
+        .first(&mut conn)
+}
+
+pub fn get_user_by_email(pool: &DbPool, email: &str) -> Result<User, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    // 邮箱按不区分大小写比较，避免只差大小写的邮箱重复注册
+    users::table.filter(lower(users::email).eq(lower(email)))
+        .select(User::as_select())
+        .first(&mut conn)
+}
+
+pub fn update_password_hash(pool: &DbPool, userid: Uuid, new_hash: &str) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(users::table.filter(users::user_id.eq(userid)))
+        .set(users::password_hash.eq(new_hash))
         .execute(&mut conn)
 }
 
-pub fn add_new_session(pool: &DbPool, new_session: &NewSession) -> Result<usize, diesel::result::Error>{
+/// 认证时使用：只返回未停用、未申请注销的用户的角色，其他情况视为 `NotFound`
+pub fn get_active_user_role(pool: &DbPool, userid: Uuid) -> Result<String, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
 
-    diesel::insert_into(user_sessions::table)
-        .values(new_session)
+    users::table.filter(users::user_id.eq(userid))
+        .filter(users::disabled_at.is_null())
+        .filter(users::deletion_scheduled_at.is_null())
+        .select(users::role)
+        .first::<String>(&mut conn)
+}
+
+/// 申请注销：记录计划删除时间并注销所有会话
+pub fn schedule_account_deletion(pool: &DbPool, userid: Uuid, scheduled_at: NaiveDateTime, revoke_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let updated = diesel::update(users::table.filter(users::user_id.eq(userid)))
+            .set(users::deletion_scheduled_at.eq(scheduled_at))
+            .execute(conn)?;
+
+        diesel::update(user_sessions::table)
+            .filter(user_sessions::user_id.eq(userid))
+            .filter(user_sessions::expires_at.gt(revoke_time))
+            .set(user_sessions::expires_at.eq(revoke_time))
+            .execute(conn)?;
+
+        Ok(updated)
+    })
+}
+
+pub fn cancel_account_deletion(pool: &DbPool, userid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(users::table.filter(users::user_id.eq(userid)))
+        .set(users::deletion_scheduled_at.eq(None::<NaiveDateTime>))
         .execute(&mut conn)
 }
 
-pub fn get_session_by_session_id(pool: &DbPool, session_uuid: Uuid) -> Result<Session, diesel::result::Error>{
+/// 彻底删除宽限期已过的账号，关联数据由外键级联删除
+pub fn purge_deleted_accounts(pool: &DbPool, purge_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
 
-    user_sessions::table.filter(user_sessions::session_id.eq(session_uuid))
-        .first::<Session>(&mut conn)
+    diesel::delete(users::table.filter(users::deletion_scheduled_at.le(purge_time)))
+        .execute(&mut conn)
+}
+
+/// 分页列出用户，`keyword` 同时匹配用户名和邮箱，返回当前页和总数
+pub fn list_users(pool: &DbPool, keyword: Option<&str>, role: Option<&str>, limit: i64, offset: i64) -> Result<(Vec<User>, i64), diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let build_query = || {
+        let mut query = users::table.into_boxed();
+        if let Some(keyword) = keyword {
+            let pattern = format!("%{}%", keyword);
+            query = query.filter(users::username.ilike(pattern.clone()).or(users::email.ilike(pattern)));
+        }
+        if let Some(role) = role {
+            query = query.filter(users::role.eq(role));
+        }
+        query
+    };
+
+    let total = build_query().count().get_result::<i64>(&mut conn)?;
+    let page = build_query()
+        .order(users::created_at.desc())
+        .limit(limit)
+        .offset(offset)
+        .select(User::as_select())
+        .load(&mut conn)?;
+
+    Ok((page, total))
 }
 
-pub fn update_expries_by_session(pool: &DbPool, session_uuid: Uuid, expries_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+pub fn update_user_role(pool: &DbPool, userid: Uuid, role: &str) -> Result<usize, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
 
-    diesel::update(user_sessions::table.filter(user_sessions::session_id.eq(session_uuid)))
-        .set(user_sessions::expires_at.eq(expries_time)) 
+    diesel::update(users::table.filter(users::user_id.eq(userid)))
+        .set(users::role.eq(role))
         .execute(&mut conn)
 }
 
-pub fn add_new_chat(pool: &DbPool, new_chat: &NewChat) -> Result<usize, diesel::result::Error>{
+/// 停用账号时同时注销该用户的所有会话；`disabled_at` 为 `None` 表示恢复账号
+pub fn set_user_disabled(pool: &DbPool, userid: Uuid, disabled_at: Option<NaiveDateTime>) -> Result<usize, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
 
-    diesel::insert_into(chats::table)
-        .values(new_chat)
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let updated = diesel::update(users::table.filter(users::user_id.eq(userid)))
+            .set(users::disabled_at.eq(disabled_at))
+            .execute(conn)?;
+
+        if let Some(revoke_time) = disabled_at {
+            diesel::update(user_sessions::table)
+                .filter(user_sessions::user_id.eq(userid))
+                .filter(user_sessions::expires_at.gt(revoke_time))
+                .set(user_sessions::expires_at.eq(revoke_time))
+                .execute(conn)?;
+        }
+
+        Ok(updated)
+    })
+}
+
+/// 使用量统计，`userid` 为 `None` 时统计全站，`since` 之后的消息计入近期消息数
+pub fn get_usage_stats(pool: &DbPool, userid: Option<Uuid>, since: NaiveDateTime) -> Result<UsageStats, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let mut chat_query = chats::table.into_boxed();
+    let mut message_query = messages::table.inner_join(chats::table).into_boxed();
+    let mut recent_query = messages::table.inner_join(chats::table)
+        .filter(messages::timestamp.ge(since))
+        .into_boxed();
+    let mut session_query = user_sessions::table
+        .filter(user_sessions::expires_at.gt(now()))
+        .filter(user_sessions::rotated_at.is_null())
+        .into_boxed();
+
+    if let Some(userid) = userid {
+        chat_query = chat_query.filter(chats::user_id.eq(userid));
+        message_query = message_query.filter(chats::user_id.eq(userid));
+        recent_query = recent_query.filter(chats::user_id.eq(userid));
+        session_query = session_query.filter(user_sessions::user_id.eq(userid));
+    }
+
+    Ok(UsageStats {
+        chat_count: chat_query.count().get_result(&mut conn)?,
+        message_count: message_query.count().get_result(&mut conn)?,
+        recent_message_count: recent_query.count().get_result(&mut conn)?,
+        active_session_count: session_query.count().get_result(&mut conn)?,
+    })
+}
+
+pub fn add_password_reset_token(pool: &DbPool, new_token: &NewPasswordResetToken) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(password_reset_tokens::table)
+        .values(new_token)
         .execute(&mut conn)
 }
 
-pub fn delete_chat(pool: &DbPool, chat_uuid: Uuid) -> Result<usize, diesel::result::Error> {
+/// 使用重置令牌修改密码：令牌只能用一次，成功后该用户的其他重置令牌和所有会话同时失效。
+/// 令牌不存在、已使用或已过期时返回 `NotFound`
+pub fn reset_password_with_token(pool: &DbPool, token_hash: &str, new_hash: &str) -> Result<Uuid, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
 
     conn.transaction::<_, diesel::result::Error, _>(|conn| {
-        diesel::delete(messages::table.filter(messages::chat_id.eq(chat_uuid)))
+        let reset_time = now();
+
+        let userid = diesel::update(
+            password_reset_tokens::table
+                .filter(password_reset_tokens::token_hash.eq(token_hash))
+                .filter(password_reset_tokens::used_at.is_null())
+                .filter(password_reset_tokens::expires_at.gt(reset_time))
+        )
+            .set(password_reset_tokens::used_at.eq(reset_time))
+            .returning(password_reset_tokens::user_id)
+            .get_result::<Uuid>(conn)?;
+
+        diesel::update(
+            password_reset_tokens::table
+                .filter(password_reset_tokens::user_id.eq(userid))
+                .filter(password_reset_tokens::used_at.is_null())
+        )
+            .set(password_reset_tokens::used_at.eq(reset_time))
+            .execute(conn)?;
+
+        diesel::update(users::table.filter(users::user_id.eq(userid)))
+            .set(users::password_hash.eq(new_hash))
+            .execute(conn)?;
+
+        diesel::update(
+            user_sessions::table
+                .filter(user_sessions::user_id.eq(userid))
+                .filter(user_sessions::expires_at.gt(reset_time))
+        )
+            .set(user_sessions::expires_at.eq(reset_time))
+            .execute(conn)?;
+
+        Ok(userid)
+    })
+}
+
+pub fn add_email_verification_token(pool: &DbPool, new_token: &NewEmailVerificationToken) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(email_verification_tokens::table)
+        .values(new_token)
+        .execute(&mut conn)
+}
+
+/// 令牌签发后用户若已修改邮箱，旧令牌不再生效。令牌无效时返回 `NotFound`
+pub fn verify_email_with_token(pool: &DbPool, token_hash: &str) -> Result<Uuid, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let verify_time = now();
+
+        let (userid, email) = diesel::update(
+            email_verification_tokens::table
+                .filter(email_verification_tokens::token_hash.eq(token_hash))
+                .filter(email_verification_tokens::used_at.is_null())
+                .filter(email_verification_tokens::expires_at.gt(verify_time))
+        )
+            .set(email_verification_tokens::used_at.eq(verify_time))
+            .returning((email_verification_tokens::user_id, email_verification_tokens::email))
+            .get_result::<(Uuid, String)>(conn)?;
+
+        let updated = diesel::update(
+            users::table
+                .filter(users::user_id.eq(userid))
+                .filter(users::email.eq(email))
+        )
+            .set(users::email_verified_at.eq(verify_time))
+            .execute(conn)?;
+
+        if updated == 0 {
+            return Err(diesel::result::Error::NotFound);
+        }
+        Ok(userid)
+    })
+}
+
+/// 写入待确认的 TOTP 密钥，确认前 `totp_enabled_at` 保持为空，登录不受影响
+pub fn set_pending_totp_secret(pool: &DbPool, userid: Uuid, secret: &str) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(
+        users::table
+            .filter(users::user_id.eq(userid))
+            .filter(users::totp_enabled_at.is_null())
+    )
+        .set((
+            users::totp_secret.eq(Some(secret)),
+            users::totp_last_used_step.eq(None::<i64>),
+        ))
+        .execute(&mut conn)
+}
+
+/// 启用 TOTP 并替换全部恢复码
+pub fn enable_totp(pool: &DbPool, userid: Uuid, used_step: i64, codes: &[NewRecoveryCode]) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::update(users::table.filter(users::user_id.eq(userid)))
+            .set((
+                users::totp_enabled_at.eq(Some(now())),
+                users::totp_last_used_step.eq(Some(used_step)),
+            ))
+            .execute(conn)?;
+
+        diesel::delete(user_recovery_codes::table.filter(user_recovery_codes::user_id.eq(userid)))
             .execute(conn)?;
 
-        diesel::delete(chats::table.filter(chats::chat_id.eq(chat_uuid)))
+        diesel::insert_into(user_recovery_codes::table)
+            .values(codes)
             .execute(conn)
     })
 }
 
-pub fn get_all_chats_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<Chat>, diesel::result::Error>{
+pub fn replace_recovery_codes(pool: &DbPool, userid: Uuid, codes: &[NewRecoveryCode]) -> Result<usize, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
 
-    chats::table.filter(chats::user_id.eq(userid))
-        .load::<Chat>(&mut conn)
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::delete(user_recovery_codes::table.filter(user_recovery_codes::user_id.eq(userid)))
+            .execute(conn)?;
+
+        diesel::insert_into(user_recovery_codes::table)
+            .values(codes)
+            .execute(conn)
+    })
 }
 
-pub fn add_new_message(pool: &DbPool, new_message: &NewMessage) -> Result<usize, diesel::result::Error>{
+pub fn disable_totp(pool: &DbPool, userid: Uuid) -> Result<usize, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
 
-    diesel::insert_into(messages::table)
-        .values(new_message)
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::delete(user_recovery_codes::table.filter(user_recovery_codes::user_id.eq(userid)))
+            .execute(conn)?;
+
+        diesel::update(users::table.filter(users::user_id.eq(userid)))
+            .set((
+                users::totp_secret.eq(None::<String>),
+                users::totp_enabled_at.eq(None::<NaiveDateTime>),
+                users::totp_last_used_step.eq(None::<i64>),
+            ))
+            .execute(conn)
+    })
+}
+
+/// 记录已完成登录的挑战令牌，同一 `jti` 再次提交时返回 0，用于防止挑战令牌重放
+pub fn mark_mfa_challenge_used(pool: &DbPool, jti: Uuid, expires_at: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(used_mfa_challenges::table.filter(used_mfa_challenges::expires_at.lt(now())))
+        .execute(&mut conn)?;
+
+    diesel::insert_into(used_mfa_challenges::table)
+        .values((used_mfa_challenges::jti.eq(jti), used_mfa_challenges::expires_at.eq(expires_at)))
+        .on_conflict_do_nothing()
         .execute(&mut conn)
 }
 
-pub fn get_all_messages_by_chat_id(pool: &DbPool, chatid: Uuid) -> Result<Vec<Message>, diesel::result::Error> {
+/// 记录已使用的时间步，同一时间步或更早的验证码再次提交时返回 0，用于防止重放
+pub fn mark_totp_step_used(pool: &DbPool, userid: Uuid, used_step: i64) -> Result<usize, diesel::result::Error> {
     let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
         diesel::result::DatabaseErrorKind::UnableToSendCommand,
         Box::new("Failed to get DB connection".to_string()),
     ))?;
-    
-    messages::table.filter(messages::chat_id.eq(chatid))
-        .load::<Message>(&mut conn)
-}
\ No newline at end of file
+
+    diesel::update(
+        users::table
+            .filter(users::user_id.eq(userid))
+            .filter(users::totp_last_used_step.is_null().or(users::totp_last_used_step.lt(used_step)))
+    )
+        .set(users::totp_last_used_step.eq(Some(used_step)))
+        .execute(&mut conn)
+}
+
+/// 恢复码只能使用一次，成功消费返回 1
+pub fn consume_recovery_code(pool: &DbPool, userid: Uuid, code_hash: &str) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(
+        user_recovery_codes::table
+            .filter(user_recovery_codes::user_id.eq(userid))
+            .filter(user_recovery_codes::code_hash.eq(code_hash))
+            .filter(user_recovery_codes::used_at.is_null())
+    )
+        .set(user_recovery_codes::used_at.eq(now()))
+        .execute(&mut conn)
+}
+
+pub fn get_login_throttles(pool: &DbPool, keys: &[String]) -> Result<Vec<LoginThrottle>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    login_throttles::table
+        .filter(login_throttles::throttle_key.eq_any(keys))
+        .load::<LoginThrottle>(&mut conn)
+}
+
+pub fn record_login_failure(pool: &DbPool, key: &str, lockout_threshold: i32) -> Result<LoginThrottle, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let previous = login_throttles::table
+            .filter(login_throttles::throttle_key.eq(key))
+            .for_update()
+            .first::<LoginThrottle>(conn)
+            .optional()?;
+
+        let throttle = LoginThrottle::record_failure(previous, key, lockout_threshold, now());
+
+        diesel::insert_into(login_throttles::table)
+            .values(&throttle)
+            .on_conflict(login_throttles::throttle_key)
+            .do_update()
+            .set(&throttle)
+            .execute(conn)?;
+
+        Ok(throttle)
+    })
+}
+
+pub fn clear_login_throttle(pool: &DbPool, key: &str) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(login_throttles::table.filter(login_throttles::throttle_key.eq(key)))
+        .execute(&mut conn)
+}
+
+/// 保存授权请求状态，顺带清理已过期的状态
+pub fn add_oidc_login_state(pool: &DbPool, login_state: &OidcLoginState) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(oidc_login_states::table.filter(oidc_login_states::expires_at.lt(now())))
+        .execute(&mut conn)?;
+
+    diesel::insert_into(oidc_login_states::table)
+        .values(login_state)
+        .execute(&mut conn)
+}
+
+/// 取出并删除授权请求状态，保证每个 state 只能回调一次。不存在或已过期时返回 `NotFound`
+pub fn take_oidc_login_state(pool: &DbPool, state: &str, provider: &str) -> Result<OidcLoginState, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(
+        oidc_login_states::table
+            .filter(oidc_login_states::state.eq(state))
+            .filter(oidc_login_states::provider.eq(provider))
+            .filter(oidc_login_states::expires_at.gt(now()))
+    )
+        .get_result::<OidcLoginState>(&mut conn)
+}
+
+/// 按外部身份查找本地用户，并记录本次登录时间
+pub fn get_user_by_identity(pool: &DbPool, provider: &str, subject: &str) -> Result<User, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let identity = user_identities::table
+        .filter(user_identities::provider.eq(provider))
+        .filter(user_identities::subject.eq(subject));
+
+    let user = identity
+        .inner_join(users::table)
+        .select(User::as_select())
+        .first(&mut conn)?;
+
+    diesel::update(identity)
+        .set(user_identities::last_login_at.eq(now()))
+        .execute(&mut conn)?;
+
+    Ok(user)
+}
+
+pub fn add_user_identity(pool: &DbPool, new_identity: &NewUserIdentity) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(user_identities::table)
+        .values(new_identity)
+        .execute(&mut conn)
+}
+
+pub fn add_user_with_identity(pool: &DbPool, new_user: &NewUser, new_identity: &NewUserIdentity) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(users::table)
+            .values(new_user)
+            .execute(conn)?;
+
+        diesel::insert_into(user_identities::table)
+            .values(new_identity)
+            .execute(conn)
+    })
+}
+
+pub fn add_api_key(pool: &DbPool, api_key: &ApiKey) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(api_keys::table)
+        .values(api_key)
+        .execute(&mut conn)
+}
+
+pub fn get_api_key_by_prefix(pool: &DbPool, key_prefix: &str) -> Result<ApiKey, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    api_keys::table.filter(api_keys::key_prefix.eq(key_prefix))
+        .first::<ApiKey>(&mut conn)
+}
+
+pub fn get_api_keys_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<ApiKey>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    api_keys::table
+        .filter(api_keys::user_id.eq(userid))
+        .filter(api_keys::revoked_at.is_null())
+        .order(api_keys::created_at.desc())
+        .load::<ApiKey>(&mut conn)
+}
+
+pub fn revoke_api_key(pool: &DbPool, key_uuid: Uuid, userid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(
+        api_keys::table
+            .filter(api_keys::api_key_id.eq(key_uuid))
+            .filter(api_keys::user_id.eq(userid))
+            .filter(api_keys::revoked_at.is_null())
+    )
+        .set(api_keys::revoked_at.eq(now()))
+        .execute(&mut conn)
+}
+
+/// 与 `touch_session` 相同，一分钟内的重复调用不再写库
+pub fn touch_api_key(pool: &DbPool, key_uuid: Uuid, used_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let threshold = used_time - chrono::Duration::minutes(1);
+    diesel::update(
+        api_keys::table
+            .filter(api_keys::api_key_id.eq(key_uuid))
+            .filter(api_keys::last_used_at.is_null().or(api_keys::last_used_at.lt(threshold)))
+    )
+        .set(api_keys::last_used_at.eq(used_time))
+        .execute(&mut conn)
+}
+
+pub fn add_new_user(pool: &DbPool, new_user: &NewUser) -> Result<usize, diesel::result::Error>{
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    
+    diesel::insert_into(users::table)
+        .values(new_user)
+        .execute(&mut conn)
+}
+
+pub fn add_new_session(pool: &DbPool, new_session: &NewSession) -> Result<usize, diesel::result::Error>{
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(user_sessions::table)
+        .values(new_session)
+        .execute(&mut conn)
+}
+
+pub fn get_session_by_session_id(pool: &DbPool, session_uuid: Uuid) -> Result<Session, diesel::result::Error>{
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    user_sessions::table.filter(user_sessions::session_id.eq(session_uuid))
+        .select(Session::as_select())
+        .first(&mut conn)
+}
+
+pub fn get_session_by_refresh_hash(pool: &DbPool, refresh_hash: &str) -> Result<Session, diesel::result::Error>{
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    user_sessions::table.filter(user_sessions::refresh_token_hash.eq(refresh_hash))
+        .select(Session::as_select())
+        .first(&mut conn)
+}
+
+/// 将旧会话标记为已轮换并写入新会话。旧会话已被轮换过时返回 `Ok(false)`，说明刷新令牌被重复使用
+pub fn rotate_session(pool: &DbPool, old_session_uuid: Uuid, new_session: &NewSession) -> Result<bool, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let rotated = diesel::update(
+            user_sessions::table
+                .filter(user_sessions::session_id.eq(old_session_uuid))
+                .filter(user_sessions::rotated_at.is_null())
+        )
+            .set(user_sessions::rotated_at.eq(now()))
+            .execute(conn)?;
+
+        if rotated == 0 {
+            return Ok(false);
+        }
+
+        diesel::insert_into(user_sessions::table)
+            .values(new_session)
+            .execute(conn)?;
+        Ok(true)
+    })
+}
+
+pub fn revoke_session_family(pool: &DbPool, family_uuid: Uuid, revoke_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(
+        user_sessions::table
+            .filter(user_sessions::family_id.eq(family_uuid))
+            .filter(user_sessions::expires_at.gt(revoke_time))
+    )
+        .set(user_sessions::expires_at.eq(revoke_time))
+        .execute(&mut conn)
+}
+
+/// 每个会话族只取最新一次轮换后的会话，即用户眼中的一次登录
+pub fn get_active_sessions_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<Session>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    user_sessions::table
+        .filter(user_sessions::user_id.eq(userid))
+        .filter(user_sessions::rotated_at.is_null())
+        .filter(user_sessions::expires_at.gt(now()))
+        .order(user_sessions::last_seen_at.desc().nulls_last())
+        .select(Session::as_select())
+        .load(&mut conn)
+}
+
+pub fn revoke_all_sessions_by_user_id(pool: &DbPool, userid: Uuid, except_family: Option<Uuid>, revoke_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let mut query = diesel::update(user_sessions::table)
+        .filter(user_sessions::user_id.eq(userid))
+        .filter(user_sessions::expires_at.gt(revoke_time))
+        .into_boxed();
+
+    if let Some(family_uuid) = except_family {
+        query = query.filter(user_sessions::family_id.ne(family_uuid));
+    }
+
+    query
+        .set(user_sessions::expires_at.eq(revoke_time))
+        .execute(&mut conn)
+}
+
+/// 更新会话的最近活跃时间，一分钟内的重复请求不再写库
+pub fn touch_session(pool: &DbPool, session_uuid: Uuid, seen_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let threshold = seen_time - chrono::Duration::minutes(1);
+    diesel::update(
+        user_sessions::table
+            .filter(user_sessions::session_id.eq(session_uuid))
+            .filter(user_sessions::last_seen_at.is_null().or(user_sessions::last_seen_at.lt(threshold)))
+    )
+        .set(user_sessions::last_seen_at.eq(seen_time))
+        .execute(&mut conn)
+}
+
+pub fn add_new_chat(pool: &DbPool, new_chat: &NewChat) -> Result<usize, diesel::result::Error>{
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(chats::table)
+        .values(new_chat)
+        .execute(&mut conn)
+}
+
+/// 用户可以查看的对话：自己的对话，或自己任教班级中学生授权共享、或已提交作业的对话。
+/// 其余一律视为 `NotFound`
+pub fn get_shared_chat(pool: &DbPool, chat_uuid: Uuid, userid: Uuid) -> Result<Chat, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let taught_classrooms = classrooms::table
+        .filter(classrooms::teacher_id.eq(userid))
+        .select(classrooms::classroom_id.nullable());
+
+    let shared_with_class = diesel::dsl::exists(
+        classroom_members::table
+            .filter(classroom_members::classroom_id.nullable().eq(chats::classroom_id))
+            .filter(classroom_members::user_id.eq(chats::user_id))
+            .filter(classroom_members::share_chats.eq(true))
+    );
+
+    let submitted = diesel::dsl::exists(
+        assignment_submissions::table
+            .filter(assignment_submissions::chat_id.eq(chats::chat_id))
+            .filter(assignment_submissions::status.ne(SUBMISSION_IN_PROGRESS))
+    );
+
+    chats::table
+        .filter(chats::chat_id.eq(chat_uuid))
+        .filter(chats::deleted_at.is_null())
+        .filter(chats::user_id.eq(userid).or(
+            chats::classroom_id.eq_any(taught_classrooms).and(shared_with_class.or(submitted))
+        ))
+        .select(Chat::as_select())
+        .first(&mut conn)
+}
+
+/// 只返回属于该用户的对话，其他用户的对话一律视为 `NotFound`
+pub fn get_chat_by_id(pool: &DbPool, chat_uuid: Uuid, userid: Uuid) -> Result<Chat, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    chats::table
+        .filter(chats::chat_id.eq(chat_uuid))
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_null())
+        .select(Chat::as_select())
+        .first(&mut conn)
+}
+
+/// 把对话移入回收站，已在回收站中的对话不再更新删除时间
+pub fn trash_chat(pool: &DbPool, chat_uuid: Uuid, userid: Uuid, deleted_time: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(chats::table
+        .filter(chats::chat_id.eq(chat_uuid))
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_null()))
+        .set(chats::deleted_at.eq(deleted_time))
+        .execute(&mut conn)
+}
+
+pub fn restore_chat(pool: &DbPool, chat_uuid: Uuid, userid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(chats::table
+        .filter(chats::chat_id.eq(chat_uuid))
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_not_null()))
+        .set(chats::deleted_at.eq(None::<NaiveDateTime>))
+        .execute(&mut conn)
+}
+
+/// 回收站中的对话，最近删除的在前
+pub fn get_trashed_chats(pool: &DbPool, userid: Uuid, limit: i64, offset: i64) -> Result<Vec<Chat>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    chats::table
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_not_null())
+        .order((chats::deleted_at.desc(), chats::chat_id.desc()))
+        .limit(limit)
+        .offset(offset)
+        .select(Chat::as_select())
+        .load(&mut conn)
+}
+
+/// 彻底删除 `deleted_before` 之前移入回收站的对话，消息和作业提交随外键级联删除
+pub fn purge_trashed_chats(pool: &DbPool, deleted_before: NaiveDateTime) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(chats::table.filter(chats::deleted_at.le(deleted_before)))
+        .execute(&mut conn)
+}
+
+/// 彻底删除回收站中的对话，未移入回收站的对话返回 NotFound
+pub fn delete_chat(pool: &DbPool, chat_uuid: Uuid, userid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let owned_chat = chats::table
+            .filter(chats::chat_id.eq(chat_uuid))
+            .filter(chats::user_id.eq(userid))
+            .filter(chats::deleted_at.is_not_null());
+
+        // 先确认归属，避免删除其他用户对话下的消息
+        owned_chat.select(chats::chat_id).first::<Uuid>(conn)?;
+
+        diesel::delete(messages::table.filter(messages::chat_id.eq(chat_uuid)))
+            .execute(conn)?;
+
+        diesel::delete(owned_chat)
+            .execute(conn)
+    })
+}
+
+pub fn get_all_chats_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<Chat>, diesel::result::Error>{
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    chats::table.filter(chats::user_id.eq(userid))
+        .select(Chat::as_select())
+        .load(&mut conn)
+}
+
+// 对话列表的排序字段
+fn chat_sort_column(sort: ChatSort) -> Box<dyn BoxableExpression<chats::table, Pg, SqlType = diesel::sql_types::Timestamp>> {
+    match sort {
+        ChatSort::Created => Box::new(chats::created_at),
+        ChatSort::Activity => Box::new(chats::last_message_at),
+    }
+}
+
+/// 置顶优先、再按排序字段倒序分页查询对话，`after` 方向先正序取出再翻转，保证返回顺序一致。
+/// 多取一条用于判断是否还有下一页
+pub fn get_chats_page(pool: &DbPool, userid: Uuid, sort: ChatSort, filter: &ChatListFilter, before: Option<ChatCursor>, after: Option<ChatCursor>, limit: i64) -> Result<(Vec<Chat>, bool), diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let mut query = chats::table
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_null())
+        .filter(chats::archived.eq(filter.archived))
+        .into_boxed();
+
+    if let Some(pinned) = filter.pinned {
+        query = query.filter(chats::pinned.eq(pinned));
+    }
+    match filter.folder_id {
+        Some(Some(folder_uuid)) => query = query.filter(chats::folder_id.eq(folder_uuid)),
+        Some(None) => query = query.filter(chats::folder_id.is_null()),
+        None => {}
+    }
+    if let Some(tag_uuid) = filter.tag_id {
+        query = query.filter(chats::chat_id.eq_any(
+            chat_tags::table.filter(chat_tags::tag_id.eq(tag_uuid)).select(chat_tags::chat_id),
+        ));
+    }
+
+    // 排序为 (pinned, 排序字段, chat_id) 的字典序，游标条件按同样的顺序比较
+    query = match (before, after) {
+        (Some(c), _) => query
+            .filter(chats::pinned.lt(c.pinned).or(chats::pinned.eq(c.pinned).and(
+                chat_sort_column(sort).lt(c.at).or(chat_sort_column(sort).eq(c.at).and(chats::chat_id.lt(c.chat_id))),
+            )))
+            .order((chats::pinned.desc(), chat_sort_column(sort).desc(), chats::chat_id.desc())),
+        (None, Some(c)) => query
+            .filter(chats::pinned.gt(c.pinned).or(chats::pinned.eq(c.pinned).and(
+                chat_sort_column(sort).gt(c.at).or(chat_sort_column(sort).eq(c.at).and(chats::chat_id.gt(c.chat_id))),
+            )))
+            .order((chats::pinned.asc(), chat_sort_column(sort).asc(), chats::chat_id.asc())),
+        (None, None) => query.order((chats::pinned.desc(), chat_sort_column(sort).desc(), chats::chat_id.desc())),
+    };
+
+    let mut chats = query.limit(limit + 1).select(Chat::as_select()).load(&mut conn)?;
+
+    let has_more = chats.len() as i64 > limit;
+    chats.truncate(limit as usize);
+    if before.is_none() && after.is_some() {
+        chats.reverse();
+    }
+
+    Ok((chats, has_more))
+}
+
+pub fn update_chat(pool: &DbPool, chat_uuid: Uuid, userid: Uuid, changes: &ChatChangeset) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(chats::table
+        .filter(chats::chat_id.eq(chat_uuid))
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_null()))
+        .set(changes)
+        .execute(&mut conn)
+}
+
+pub fn add_folder(pool: &DbPool, folder: &Folder) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    diesel::insert_into(folders::table)
+        .values(folder)
+        .execute(&mut conn)
+}
+
+pub fn get_folder(pool: &DbPool, folder_uuid: Uuid, userid: Uuid) -> Result<Folder, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    folders::table
+        .filter(folders::folder_id.eq(folder_uuid))
+        .filter(folders::user_id.eq(userid))
+        .first::<Folder>(&mut conn)
+}
+
+pub fn get_folders_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<Folder>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    folders::table
+        .filter(folders::user_id.eq(userid))
+        .order((folders::name.asc(), folders::folder_id.asc()))
+        .load::<Folder>(&mut conn)
+}
+
+/// 从该文件夹开始逐级向上的文件夹 ID，第一个是它自己，用于移动文件夹时检查环
+pub fn get_folder_path(pool: &DbPool, folder_uuid: Uuid) -> Result<Vec<Uuid>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    let mut path = vec![folder_uuid];
+    let mut current = folder_uuid;
+    // 正常数据不会成环，这里限制层数防止异常数据导致死循环
+    while path.len() <= 100 {
+        let parent = folders::table
+            .filter(folders::folder_id.eq(current))
+            .select(folders::parent_id)
+            .first::<Option<Uuid>>(&mut conn)?;
+        match parent {
+            Some(parent) if !path.contains(&parent) => {
+                path.push(parent);
+                current = parent;
+            }
+            _ => break,
+        }
+    }
+    Ok(path)
+}
+
+pub fn update_folder(pool: &DbPool, folder_uuid: Uuid, changes: &FolderChangeset) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    diesel::update(folders::table.filter(folders::folder_id.eq(folder_uuid)))
+        .set(changes)
+        .execute(&mut conn)
+}
+
+/// 子文件夹随外键级联删除，其中的对话回到顶层
+pub fn delete_folder(pool: &DbPool, folder_uuid: Uuid, userid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    diesel::delete(folders::table
+        .filter(folders::folder_id.eq(folder_uuid))
+        .filter(folders::user_id.eq(userid)))
+        .execute(&mut conn)
+}
+
+pub fn add_tag(pool: &DbPool, tag: &Tag) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    diesel::insert_into(tags::table)
+        .values(tag)
+        .execute(&mut conn)
+}
+
+/// 用户的全部标签及各自关联的对话数（不含回收站中的对话）
+pub fn get_tags_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<(Tag, i64)>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    tags::table
+        .left_join(chat_tags::table.inner_join(chats::table).on(
+            chat_tags::tag_id.eq(tags::tag_id).and(chats::deleted_at.is_null()),
+        ))
+        .filter(tags::user_id.eq(userid))
+        .group_by(tags::tag_id)
+        .select((tags::all_columns, diesel::dsl::count(chat_tags::chat_id.nullable())))
+        .order(tags::name.asc())
+        .load::<(Tag, i64)>(&mut conn)
+}
+
+pub fn rename_tag(pool: &DbPool, tag_uuid: Uuid, userid: Uuid, name: &str) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    diesel::update(tags::table
+        .filter(tags::tag_id.eq(tag_uuid))
+        .filter(tags::user_id.eq(userid)))
+        .set(tags::name.eq(name))
+        .execute(&mut conn)
+}
+
+pub fn delete_tag(pool: &DbPool, tag_uuid: Uuid, userid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    diesel::delete(tags::table
+        .filter(tags::tag_id.eq(tag_uuid))
+        .filter(tags::user_id.eq(userid)))
+        .execute(&mut conn)
+}
+
+/// 一批对话上的标签，返回 (chat_id, 标签)
+pub fn get_tags_for_chats(pool: &DbPool, chat_uuids: &[Uuid]) -> Result<Vec<(Uuid, Tag)>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    chat_tags::table
+        .inner_join(tags::table)
+        .filter(chat_tags::chat_id.eq_any(chat_uuids))
+        .select((chat_tags::chat_id, tags::all_columns))
+        .order(tags::name.asc())
+        .load::<(Uuid, Tag)>(&mut conn)
+}
+
+// 确认这些对话都属于该用户且不在回收站中，否则返回 NotFound
+fn check_owned_chats(conn: &mut PgConnection, userid: Uuid, chat_uuids: &[Uuid]) -> Result<(), diesel::result::Error> {
+    let owned = chats::table
+        .filter(chats::chat_id.eq_any(chat_uuids))
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_null())
+        .count()
+        .get_result::<i64>(conn)?;
+
+    if owned != chat_uuids.len() as i64 {
+        return Err(diesel::result::Error::NotFound);
+    }
+    Ok(())
+}
+
+/// 批量移动对话，任何一个对话不可用时整体不生效。`folder_uuid` 需事先确认属于该用户
+pub fn move_chats(pool: &DbPool, userid: Uuid, chat_uuids: &[Uuid], folder_uuid: Option<Uuid>) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    conn.transaction(|conn| {
+        check_owned_chats(conn, userid, chat_uuids)?;
+
+        diesel::update(chats::table.filter(chats::chat_id.eq_any(chat_uuids)))
+            .set(chats::folder_id.eq(folder_uuid))
+            .execute(conn)
+    })
+}
+
+/// 批量添加和移除标签，添加时按名称创建不存在的标签；任何一个对话不可用时整体不生效
+pub fn tag_chats(pool: &DbPool, userid: Uuid, chat_uuids: &[Uuid], add: &[String], remove: &[String]) -> Result<(), diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    conn.transaction(|conn| {
+        check_owned_chats(conn, userid, chat_uuids)?;
+
+        if !add.is_empty() {
+            let new_tags: Vec<Tag> = add.iter().map(|name| Tag::new(userid, name)).collect();
+            diesel::insert_into(tags::table)
+                .values(&new_tags)
+                .on_conflict((tags::user_id, tags::name))
+                .do_nothing()
+                .execute(conn)?;
+
+            let tag_uuids = tags::table
+                .filter(tags::user_id.eq(userid))
+                .filter(tags::name.eq_any(add))
+                .select(tags::tag_id)
+                .load::<Uuid>(conn)?;
+
+            let links: Vec<ChatTag> = chat_uuids.iter()
+                .flat_map(|chat_uuid| tag_uuids.iter().map(move |tag_uuid| ChatTag { chat_id: *chat_uuid, tag_id: *tag_uuid }))
+                .collect();
+            diesel::insert_into(chat_tags::table)
+                .values(&links)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+
+        if !remove.is_empty() {
+            let tag_uuids = tags::table
+                .filter(tags::user_id.eq(userid))
+                .filter(tags::name.eq_any(remove))
+                .select(tags::tag_id);
+
+            diesel::delete(chat_tags::table
+                .filter(chat_tags::chat_id.eq_any(chat_uuids))
+                .filter(chat_tags::tag_id.eq_any(tag_uuids)))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// 在用户的对话标题和消息内容中全文检索，分词与高亮由迁移中的 chat_search_* 函数完成。
+/// 先排序分页再生成摘要，避免对全部命中计算 ts_headline
+pub fn search_chats(pool: &DbPool, userid: Uuid, keywords: &str, limit: i64, offset: i64) -> Result<Vec<SearchHit>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::sql_query(r#"
+        WITH query AS (SELECT chat_search_query($2) AS q),
+        hits AS (
+            SELECT c.chat_id, c.title, NULL::uuid AS message_id, NULL::bigint AS seq, NULL::varchar AS role,
+                   c.title AS content, ts_rank(c.search_vector, query.q) AS rank, c.last_message_at AS matched_at
+            FROM chats c, query
+            WHERE c.user_id = $1 AND c.deleted_at IS NULL AND c.search_vector @@ query.q
+            UNION ALL
+            SELECT c.chat_id, c.title, m.message_id, m.seq, m.role,
+                   m.content, ts_rank(m.search_vector, query.q), COALESCE(m.timestamp, c.created_at)
+            FROM messages m JOIN chats c ON c.chat_id = m.chat_id, query
+            WHERE c.user_id = $1 AND c.deleted_at IS NULL AND m.search_vector @@ query.q
+        ),
+        page AS (
+            SELECT hits.*, COUNT(*) OVER () AS total
+            FROM hits
+            ORDER BY rank DESC, matched_at DESC, chat_id, seq NULLS FIRST
+            LIMIT $3 OFFSET $4
+        )
+        SELECT page.chat_id, page.title, page.message_id, page.seq, page.role,
+               chat_search_headline(page.content, query.q) AS snippet, page.rank, page.matched_at, page.total
+        FROM page, query
+        ORDER BY page.rank DESC, page.matched_at DESC, page.chat_id, page.seq NULLS FIRST
+    "#)
+        .bind::<diesel::sql_types::Uuid, _>(userid)
+        .bind::<diesel::sql_types::Text, _>(keywords)
+        .bind::<diesel::sql_types::Int8, _>(limit)
+        .bind::<diesel::sql_types::Int8, _>(offset)
+        .load::<SearchHit>(&mut conn)
+}
+
+/// 写入消息并接在当前分支的最后一条消息后面，返回其在对话中的序号
+pub fn add_new_message(pool: &DbPool, new_message: &NewMessage) -> Result<i64, diesel::result::Error>{
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    conn.transaction(|conn| {
+        // 锁住对话行，保证并发写入时读到的当前分支末尾不会过期
+        let parent = chats::table
+            .filter(chats::chat_id.eq(new_message.chat_id))
+            .select(chats::active_message_id)
+            .for_update()
+            .first::<Option<Uuid>>(conn)?;
+
+        insert_message(conn, new_message, parent)
+    })
+}
+
+/// 写入消息并接在指定的父消息后面，`parent` 为 `None` 时作为根消息。用于编辑消息产生新分支，
+/// 以及把回复接在对应的提问后面
+pub fn add_branch_message(pool: &DbPool, new_message: &NewMessage, parent: Option<Uuid>) -> Result<i64, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    conn.transaction(|conn| insert_message(conn, new_message, parent))
+}
+
+// 递增对话的消息序号并写入消息，返回分配的序号。
+// 更新 chats 会锁住该行，同一对话的并发写入按顺序拿到序号；同时更新最后活跃时间，
+// 并把新消息设为当前分支的末尾
+fn insert_message(conn: &mut PgConnection, new_message: &NewMessage, parent: Option<Uuid>) -> Result<i64, diesel::result::Error> {
+    let seq = diesel::update(chats::table.filter(chats::chat_id.eq(new_message.chat_id)))
+        .set((
+            chats::last_message_seq.eq(chats::last_message_seq + 1),
+            chats::last_message_at.eq(new_message.timestamp.unwrap_or_else(now)),
+        ))
+        .returning(chats::last_message_seq)
+        .get_result::<i64>(conn)?;
+
+    diesel::insert_into(messages::table)
+        .values((new_message, messages::seq.eq(seq), messages::parent_message_id.eq(parent)))
+        .execute(conn)?;
+
+    diesel::update(chats::table.filter(chats::chat_id.eq(new_message.chat_id)))
+        .set(chats::active_message_id.eq(new_message.message_id))
+        .execute(conn)?;
+
+    Ok(seq)
+}
+
+pub fn get_message(pool: &DbPool, chatid: Uuid, message_uuid: Uuid) -> Result<Message, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    messages::table
+        .filter(messages::chat_id.eq(chatid))
+        .filter(messages::message_id.eq(message_uuid))
+        .first::<Message>(&mut conn)
+}
+
+/// 与 `message` 同一父消息下的全部消息（含自身），按创建顺序排列。重新生成的回答互为同级
+pub fn get_sibling_messages(pool: &DbPool, message: &Message) -> Result<Vec<Message>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    messages::table
+        .filter(messages::chat_id.eq(message.chat_id))
+        .filter(messages::parent_message_id.is_not_distinct_from(message.parent_message_id))
+        .order(messages::seq.asc())
+        .load::<Message>(&mut conn)
+}
+
+/// 从 `leaf` 向上回溯到根的分支中最近的 `limit` 条消息，按序号正序返回
+pub fn get_branch_messages(pool: &DbPool, leaf: Uuid, limit: i64) -> Result<Vec<Message>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    load_branch(&mut conn, leaf, Some(limit))
+}
+
+// `limit` 为 `None` 时返回整条分支
+fn load_branch(conn: &mut PgConnection, leaf: Uuid, limit: Option<i64>) -> Result<Vec<Message>, diesel::result::Error> {
+    diesel::sql_query(r#"
+        WITH RECURSIVE branch AS (
+            SELECT message_id, chat_id, role, content, timestamp, seq, parent_message_id
+            FROM messages WHERE message_id = $1
+            UNION ALL
+            SELECT m.message_id, m.chat_id, m.role, m.content, m.timestamp, m.seq, m.parent_message_id
+            FROM messages m JOIN branch b ON m.message_id = b.parent_message_id
+        )
+        SELECT * FROM (SELECT * FROM branch ORDER BY seq DESC LIMIT $2) recent
+        ORDER BY seq
+    "#)
+        .bind::<diesel::sql_types::Uuid, _>(leaf)
+        .bind::<diesel::sql_types::Nullable<diesel::sql_types::Int8>, _>(limit)
+        .load::<Message>(conn)
+}
+
+/// 创建分叉对话，并把来源对话中从根到 `message_uuid` 的分支复制进去，返回复制的消息数。
+/// 复制的消息保留原时间，对话的最后活跃时间为分叉时间
+pub fn fork_chat(pool: &DbPool, new_chat: &NewChat, message_uuid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction(|conn| {
+        let branch = load_branch(conn, message_uuid, None)?;
+
+        diesel::insert_into(chats::table)
+            .values(new_chat)
+            .execute(conn)?;
+
+        let mut parent = None;
+        for msg in &branch {
+            let mut copy = NewMessage::new(new_chat.chat_id, &msg.role, &msg.content);
+            copy.timestamp = msg.timestamp;
+            insert_message(conn, &copy, parent)?;
+            parent = Some(copy.message_id);
+        }
+
+        diesel::update(chats::table.filter(chats::chat_id.eq(new_chat.chat_id)))
+            .set(chats::last_message_at.eq(now()))
+            .execute(conn)?;
+
+        Ok(branch.len())
+    })
+}
+
+/// 切换到 `message_uuid` 所在的分支：沿最新的子消息一直向下，把到达的末尾设为当前分支的末尾
+pub fn activate_branch(pool: &DbPool, chatid: Uuid, message_uuid: Uuid) -> Result<Uuid, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    conn.transaction(|conn| {
+        let mut leaf = message_uuid;
+        while let Some(child) = messages::table
+            .filter(messages::parent_message_id.eq(leaf))
+            .order(messages::seq.desc())
+            .select(messages::message_id)
+            .first::<Uuid>(conn)
+            .optional()?
+        {
+            leaf = child;
+        }
+
+        diesel::update(chats::table.filter(chats::chat_id.eq(chatid)))
+            .set(chats::active_message_id.eq(leaf))
+            .execute(conn)?;
+
+        Ok(leaf)
+    })
+}
+
+pub fn get_all_messages_by_chat_id(pool: &DbPool, chatid: Uuid, userid: Uuid) -> Result<Vec<Message>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    
+    messages::table
+        .inner_join(chats::table)
+        .filter(messages::chat_id.eq(chatid))
+        .filter(chats::user_id.eq(userid))
+        .order(messages::seq.asc())
+        .select(messages::all_columns)
+        .load::<Message>(&mut conn)
+}
+
+/// 当前分支上按序号倒序取 `before_seq` 之前的最多 `limit` 条消息，翻转为正序返回，
+/// 并返回是否还有更早的消息。分支从对话的 `active_message_id` 向上回溯得到
+pub fn get_messages_page(pool: &DbPool, chatid: Uuid, userid: Uuid, before_seq: Option<i64>, limit: i64) -> Result<(Vec<BranchMessage>, bool), diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+    let mut msgs = diesel::sql_query(r#"
+        WITH RECURSIVE branch AS (
+            SELECT m.message_id, m.chat_id, m.role, m.content, m.timestamp, m.seq, m.parent_message_id
+            FROM messages m JOIN chats c ON c.active_message_id = m.message_id
+            WHERE c.chat_id = $1 AND c.user_id = $2 AND c.deleted_at IS NULL
+            UNION ALL
+            SELECT m.message_id, m.chat_id, m.role, m.content, m.timestamp, m.seq, m.parent_message_id
+            FROM messages m JOIN branch b ON m.message_id = b.parent_message_id
+        )
+        SELECT page.*, siblings.sibling_ids
+        FROM (
+            SELECT * FROM branch
+            WHERE $3::BIGINT IS NULL OR seq < $3
+            ORDER BY seq DESC
+            LIMIT $4
+        ) page
+        CROSS JOIN LATERAL (
+            SELECT array_agg(s.message_id ORDER BY s.seq) AS sibling_ids
+            FROM messages s
+            WHERE s.chat_id = page.chat_id AND s.parent_message_id IS NOT DISTINCT FROM page.parent_message_id
+        ) siblings
+        ORDER BY page.seq DESC
+    "#)
+        .bind::<diesel::sql_types::Uuid, _>(chatid)
+        .bind::<diesel::sql_types::Uuid, _>(userid)
+        .bind::<diesel::sql_types::Nullable<diesel::sql_types::Int8>, _>(before_seq)
+        .bind::<diesel::sql_types::Int8, _>(limit + 1)
+        .load::<BranchMessage>(&mut conn)?;
+
+    let has_more = msgs.len() as i64 > limit;
+    msgs.truncate(limit as usize);
+    msgs.reverse();
+
+    Ok((msgs, has_more))
+}
+
+pub fn add_classroom(pool: &DbPool, classroom: &Classroom) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(classrooms::table)
+        .values(classroom)
+        .execute(&mut conn)
+}
+
+pub fn get_classroom_by_id(pool: &DbPool, classroom_uuid: Uuid) -> Result<Classroom, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    classrooms::table.filter(classrooms::classroom_id.eq(classroom_uuid))
+        .first::<Classroom>(&mut conn)
+}
+
+pub fn get_classroom_by_invite_code(pool: &DbPool, code: &str) -> Result<Classroom, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    classrooms::table.filter(classrooms::invite_code.eq(code))
+        .first::<Classroom>(&mut conn)
+}
+
+/// 只返回该用户任教或已加入的班级，其他班级一律视为 `NotFound`
+pub fn get_classroom_for_user(pool: &DbPool, classroom_uuid: Uuid, userid: Uuid) -> Result<Classroom, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let is_member = diesel::dsl::exists(
+        classroom_members::table
+            .filter(classroom_members::classroom_id.eq(classroom_uuid))
+            .filter(classroom_members::user_id.eq(userid))
+    );
+
+    classrooms::table
+        .filter(classrooms::classroom_id.eq(classroom_uuid))
+        .filter(classrooms::teacher_id.eq(userid).or(is_member))
+        .first::<Classroom>(&mut conn)
+}
+
+pub fn get_classrooms_by_teacher_id(pool: &DbPool, teacherid: Uuid) -> Result<Vec<Classroom>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    classrooms::table.filter(classrooms::teacher_id.eq(teacherid))
+        .order(classrooms::created_at.desc())
+        .load::<Classroom>(&mut conn)
+}
+
+pub fn get_joined_classrooms(pool: &DbPool, userid: Uuid) -> Result<Vec<(Classroom, ClassroomMember)>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    classrooms::table.inner_join(classroom_members::table)
+        .filter(classroom_members::user_id.eq(userid))
+        .order(classroom_members::joined_at.desc())
+        .load::<(Classroom, ClassroomMember)>(&mut conn)
+}
+
+pub fn update_classroom(pool: &DbPool, classroom_uuid: Uuid, changes: &ClassroomChangeset) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(classrooms::table.filter(classrooms::classroom_id.eq(classroom_uuid)))
+        .set(changes)
+        .execute(&mut conn)
+}
+
+pub fn update_classroom_invite_code(pool: &DbPool, classroom_uuid: Uuid, code: &str) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(classrooms::table.filter(classrooms::classroom_id.eq(classroom_uuid)))
+        .set(classrooms::invite_code.eq(code))
+        .execute(&mut conn)
+}
+
+pub fn delete_classroom(pool: &DbPool, classroom_uuid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(classrooms::table.filter(classrooms::classroom_id.eq(classroom_uuid)))
+        .execute(&mut conn)
+}
+
+/// 已是成员时不重复加入，返回 0
+pub fn add_classroom_member(pool: &DbPool, member: &ClassroomMember) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(classroom_members::table)
+        .values(member)
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+}
+
+pub fn remove_classroom_member(pool: &DbPool, classroom_uuid: Uuid, userid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(
+        classroom_members::table
+            .filter(classroom_members::classroom_id.eq(classroom_uuid))
+            .filter(classroom_members::user_id.eq(userid))
+    )
+        .execute(&mut conn)
+}
+
+pub fn set_classroom_share_chats(pool: &DbPool, classroom_uuid: Uuid, userid: Uuid, share_chats: bool) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::update(
+        classroom_members::table
+            .filter(classroom_members::classroom_id.eq(classroom_uuid))
+            .filter(classroom_members::user_id.eq(userid))
+    )
+        .set(classroom_members::share_chats.eq(share_chats))
+        .execute(&mut conn)
+}
+
+pub fn get_classroom_member(pool: &DbPool, classroom_uuid: Uuid, userid: Uuid) -> Result<ClassroomMember, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    classroom_members::table
+        .filter(classroom_members::classroom_id.eq(classroom_uuid))
+        .filter(classroom_members::user_id.eq(userid))
+        .first::<ClassroomMember>(&mut conn)
+}
+
+/// 班级成员及其用户名
+pub fn get_classroom_members(pool: &DbPool, classroom_uuid: Uuid) -> Result<Vec<(ClassroomMember, String)>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    classroom_members::table.inner_join(users::table)
+        .filter(classroom_members::classroom_id.eq(classroom_uuid))
+        .order(classroom_members::joined_at.asc())
+        .select((classroom_members::all_columns, users::username))
+        .load::<(ClassroomMember, String)>(&mut conn)
+}
+
+/// 按成员汇总班级内对话的数量、消息数和最近一条消息的时间
+pub fn get_classroom_activity(pool: &DbPool, classroom_uuid: Uuid) -> Result<Vec<MemberActivity>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    chats::table.left_join(messages::table)
+        .filter(chats::classroom_id.eq(classroom_uuid))
+        .filter(chats::deleted_at.is_null())
+        .group_by(chats::user_id)
+        .select((
+            chats::user_id,
+            diesel::dsl::count_distinct(chats::chat_id),
+            diesel::dsl::count(messages::message_id.nullable()),
+            diesel::dsl::max(messages::timestamp.nullable()),
+        ))
+        .load::<MemberActivity>(&mut conn)
+}
+
+/// 某个成员在班级内的对话，附带消息数和最近一条消息的时间
+pub fn get_classroom_member_chats(pool: &DbPool, classroom_uuid: Uuid, userid: Uuid) -> Result<Vec<(Chat, i64, Option<NaiveDateTime>)>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    chats::table.left_join(messages::table)
+        .filter(chats::classroom_id.eq(classroom_uuid))
+        .filter(chats::user_id.eq(userid))
+        .filter(chats::deleted_at.is_null())
+        .group_by(chats::chat_id)
+        .select((
+            Chat::as_select(),
+            diesel::dsl::count(messages::message_id.nullable()),
+            diesel::dsl::max(messages::timestamp.nullable()),
+        ))
+        .order(chats::created_at.desc())
+        .load::<(Chat, i64, Option<NaiveDateTime>)>(&mut conn)
+}
+
+pub fn add_assignment(pool: &DbPool, assignment: &Assignment) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(assignments::table)
+        .values(assignment)
+        .execute(&mut conn)
+}
+
+pub fn get_assignment_by_id(pool: &DbPool, assignment_uuid: Uuid) -> Result<Assignment, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    assignments::table.filter(assignments::assignment_id.eq(assignment_uuid))
+        .first::<Assignment>(&mut conn)
+}
+
+pub fn get_assignments_by_classroom_id(pool: &DbPool, classroom_uuid: Uuid) -> Result<Vec<Assignment>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    assignments::table.filter(assignments::classroom_id.eq(classroom_uuid))
+        .order(assignments::created_at.desc())
+        .load::<Assignment>(&mut conn)
+}
+
+pub fn delete_assignment(pool: &DbPool, assignment_uuid: Uuid) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::delete(assignments::table.filter(assignments::assignment_id.eq(assignment_uuid)))
+        .execute(&mut conn)
+}
+
+/// 开始作业：在一个事务中创建对话、写入题目消息和提交记录
+pub fn start_assignment(pool: &DbPool, new_chat: &NewChat, seed_message: &NewMessage, submission: &AssignmentSubmission) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(chats::table)
+            .values(new_chat)
+            .execute(conn)?;
+
+        insert_message(conn, seed_message, None)?;
+
+        diesel::insert_into(assignment_submissions::table)
+            .values(submission)
+            .execute(conn)
+    })
+}
+
+pub fn get_submission(pool: &DbPool, assignment_uuid: Uuid, userid: Uuid) -> Result<AssignmentSubmission, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    assignment_submissions::table
+        .filter(assignment_submissions::assignment_id.eq(assignment_uuid))
+        .filter(assignment_submissions::user_id.eq(userid))
+        .first::<AssignmentSubmission>(&mut conn)
+}
+
+pub fn get_submissions_by_user_id(pool: &DbPool, assignment_uuids: &[Uuid], userid: Uuid) -> Result<Vec<AssignmentSubmission>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    assignment_submissions::table
+        .filter(assignment_submissions::assignment_id.eq_any(assignment_uuids))
+        .filter(assignment_submissions::user_id.eq(userid))
+        .load::<AssignmentSubmission>(&mut conn)
+}
+
+pub fn get_submissions_by_assignment_id(pool: &DbPool, assignment_uuid: Uuid) -> Result<Vec<AssignmentSubmission>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    assignment_submissions::table
+        .filter(assignment_submissions::assignment_id.eq(assignment_uuid))
+        .load::<AssignmentSubmission>(&mut conn)
+}
+
+/// 按状态流转更新提交记录，当前状态不是 `from_status` 时不修改并返回 0
+pub fn update_submission_status(
+    pool: &DbPool,
+    assignment_uuid: Uuid,
+    userid: Uuid,
+    from_status: &str,
+    to_status: &str,
+    feedback: Option<&str>,
+) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let target = assignment_submissions::table
+        .filter(assignment_submissions::assignment_id.eq(assignment_uuid))
+        .filter(assignment_submissions::user_id.eq(userid))
+        .filter(assignment_submissions::status.eq(from_status));
+
+    if to_status == SUBMISSION_REVIEWED {
+        diesel::update(target)
+            .set((
+                assignment_submissions::status.eq(to_status),
+                assignment_submissions::reviewed_at.eq(now()),
+                assignment_submissions::feedback.eq(feedback),
+            ))
+            .execute(&mut conn)
+    } else {
+        diesel::update(target)
+            .set((
+                assignment_submissions::status.eq(to_status),
+                assignment_submissions::submitted_at.eq(now()),
+            ))
+            .execute(&mut conn)
+    }
+}
+
+pub fn get_chat_activity(pool: &DbPool, chat_uuids: &[Uuid]) -> Result<Vec<ChatActivity>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    messages::table
+        .filter(messages::chat_id.eq_any(chat_uuids))
+        .group_by(messages::chat_id)
+        .select((
+            messages::chat_id,
+            diesel::dsl::count(messages::message_id),
+            diesel::dsl::max(messages::timestamp),
+        ))
+        .load::<ChatActivity>(&mut conn)
+}
+
+pub fn add_ocr_record(pool: &DbPool, record: &OcrRecord) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(ocr_records::table)
+        .values(record)
+        .execute(&mut conn)
+}
+
+pub fn get_ocr_records_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<OcrRecord>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    ocr_records::table.filter(ocr_records::user_id.eq(userid))
+        .order(ocr_records::created_at.asc())
+        .load::<OcrRecord>(&mut conn)
+}
+
+/// 包括已过期和已轮换的会话，用于导出个人数据
+pub fn get_sessions_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<Session>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    user_sessions::table.filter(user_sessions::user_id.eq(userid))
+        .order(user_sessions::created_at.asc())
+        .select(Session::as_select())
+        .load(&mut conn)
+}
+
+pub fn get_identities_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<UserIdentity>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    user_identities::table.filter(user_identities::user_id.eq(userid))
+        .select(UserIdentity::as_select())
+        .load(&mut conn)
+}
+
+/// 该用户所有对话中的消息，按对话和序号排序
+pub fn get_all_messages_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<Message>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    messages::table.inner_join(chats::table)
+        .filter(chats::user_id.eq(userid))
+        .order((messages::chat_id.asc(), messages::seq.asc()))
+        .select(messages::all_columns)
+        .load::<Message>(&mut conn)
+}
+
+pub fn get_all_submissions_by_user_id(pool: &DbPool, userid: Uuid) -> Result<Vec<AssignmentSubmission>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    assignment_submissions::table
+        .filter(assignment_submissions::user_id.eq(userid))
+        .load::<AssignmentSubmission>(&mut conn)
+}
+
+pub fn add_audit_event(pool: &DbPool, event: &AuditEvent) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    diesel::insert_into(audit_events::table)
+        .values(event)
+        .execute(&mut conn)
+}
+
+/// 按条件查询审计日志，按时间倒序返回，`event_type` 以 `.*` 结尾时按前缀匹配
+pub fn query_audit_events(
+    pool: &DbPool,
+    actor: Option<Uuid>,
+    event_type: Option<&str>,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|_| diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::UnableToSendCommand,
+        Box::new("Failed to get DB connection".to_string()),
+    ))?;
+
+    let mut query = audit_events::table.into_boxed();
+
+    if let Some(actor) = actor {
+        query = query.filter(audit_events::actor_id.eq(actor));
+    }
+    if let Some(event_type) = event_type {
+        query = match event_type.strip_suffix('*') {
+            Some(prefix) => query.filter(audit_events::event_type.like(format!("{}%", prefix.replace('%', "\\%").replace('_', "\\_")))),
+            None => query.filter(audit_events::event_type.eq(event_type.to_string())),
+        };
+    }
+    if let Some(from) = from {
+        query = query.filter(audit_events::created_at.ge(from));
+    }
+    if let Some(to) = to {
+        query = query.filter(audit_events::created_at.lt(to));
+    }
+
+    query
+        .order(audit_events::created_at.desc())
+        .limit(limit)
+        .load::<AuditEvent>(&mut conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_chat, create_user, delete_user, test_pool};
+
+    #[test]
+    #[ignore = "需要 TEST_DATABASE_URL"]
+    fn other_users_chat_is_not_found() {
+        let pool = test_pool();
+        let owner = create_user(&pool);
+        let other = create_user(&pool);
+        let chat_uuid = create_chat(&pool, owner);
+
+        assert!(get_chat_by_id(&pool, chat_uuid, owner).is_ok());
+        assert!(matches!(get_chat_by_id(&pool, chat_uuid, other), Err(diesel::result::Error::NotFound)));
+
+        delete_user(&pool, owner);
+        delete_user(&pool, other);
+    }
+
+    #[test]
+    #[ignore = "需要 TEST_DATABASE_URL"]
+    fn other_users_messages_are_not_returned() {
+        let pool = test_pool();
+        let owner = create_user(&pool);
+        let other = create_user(&pool);
+        let chat_uuid = create_chat(&pool, owner);
+
+        assert_eq!(get_all_messages_by_chat_id(&pool, chat_uuid, owner).unwrap().len(), 1);
+        assert!(get_all_messages_by_chat_id(&pool, chat_uuid, other).unwrap().is_empty());
+
+        let (page, _) = get_messages_page(&pool, chat_uuid, other, None, 50).unwrap();
+        assert!(page.is_empty());
+
+        delete_user(&pool, owner);
+        delete_user(&pool, other);
+    }
+
+    #[test]
+    #[ignore = "需要 TEST_DATABASE_URL"]
+    fn other_user_cannot_trash_or_delete_chat() {
+        let pool = test_pool();
+        let owner = create_user(&pool);
+        let other = create_user(&pool);
+        let chat_uuid = create_chat(&pool, owner);
+
+        assert_eq!(trash_chat(&pool, chat_uuid, other, now()).unwrap(), 0);
+        assert_eq!(trash_chat(&pool, chat_uuid, owner, now()).unwrap(), 1);
+
+        assert!(matches!(delete_chat(&pool, chat_uuid, other), Err(diesel::result::Error::NotFound)));
+        assert_eq!(get_all_messages_by_chat_id(&pool, chat_uuid, owner).unwrap().len(), 1);
+        assert_eq!(delete_chat(&pool, chat_uuid, owner).unwrap(), 1);
+
+        delete_user(&pool, owner);
+        delete_user(&pool, other);
+    }
+}
@@ -2,306 +2,3660 @@ use std::str::FromStr;
 
 use reqwest::Client;
 use actix_web::web::Data;
-use actix_web::{HttpMessage, HttpRequest};
 use actix_web::{web, HttpResponse, Responder, http::header};
+use actix_web::HttpRequest;
+use actix_web::cookie::{Cookie, SameSite, time::Duration as CookieDuration};
 use crate::models::*;
 use crate::database::*;
-use crate::utils::{generate_jwt, generate_uuid};
+use crate::utils::{generate_jwt, generate_uuid, generate_token, hash_token, hash_password, ACCESS_TOKEN_TTL_SECONDS, MIN_PASSWORD_LENGTH, PASSWORD_RESET_TTL_MINUTES};
+use crate::utils::{chat_trash_retention_days, ACCOUNT_DELETION_GRACE_DAYS};
+use crate::utils::{is_valid_email, env_flag, generate_invite_code, EMAIL_VERIFICATION_TTL_HOURS};
+use crate::utils::{generate_challenge_jwt, decode_challenge_jwt, MFA_CHALLENGE_TTL_SECONDS, OIDC_STATE_TTL_MINUTES};
+use crate::totp::{generate_secret as generate_totp_secret, otpauth_uri, verify_code, generate_recovery_codes, normalize_recovery_code};
+use chrono::Utc;
+use crate::utils::{API_KEY_PREFIX, API_KEY_SCOPES, SCOPE_CHAT_READ, SCOPE_CHAT_WRITE, SCOPE_OCR};
+use crate::utils::{SUBMISSION_IN_PROGRESS, SUBMISSION_SUBMITTED, SUBMISSION_REVIEWED};
+use crate::utils::{trusted_proxies, dummy_password_hash, LOGIN_USER_LOCKOUT_THRESHOLD, LOGIN_IP_LOCKOUT_THRESHOLD};
 use uuid::Uuid;
 use serde_json::{json, Value};
 use bcrypt::verify;
 use futures::TryStreamExt;
 use tokio::sync::mpsc;
 use bytes::Bytes;
-use crate::utils::{decode_jwt, now};
+use crate::utils::now;
 use crate::xunfei_ocr::img2latex;
+use crate::oidc;
+use crate::middleware::{AuthenticatedUser, SessionUser};
+use crate::mailer::Mailer;
+use crate::audit;
+use crate::rbac::{Permission, Role};
+use std::env;
+use std::net::IpAddr;
 
+/// 每次生成的恢复码数量
+const RECOVERY_CODE_COUNT: usize = 10;
+/// 使用量统计中“近期”的天数
+const USAGE_RECENT_DAYS: i64 = 7;
+/// 第三方登录期间保存 state 的 Cookie
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+/// 转发给模型服务的分支历史消息条数上限
+const STREAM_HISTORY_LIMIT: i64 = 20;
 
-pub async fn options_handler() -> impl Responder {
-    HttpResponse::Ok()
-        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
-        .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, "POST, GET, OPTIONS"))
-        .insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization"))
-        .finish()
+
+/// 登录失败时对用户名和 IP 分别计数，不区分用户不存在与密码错误
+fn login_throttle_keys(req: &HttpRequest, username: &str) -> Vec<(String, i32)> {
+    let mut keys = vec![(LoginThrottle::user_key(username), LOGIN_USER_LOCKOUT_THRESHOLD)];
+    if let Some(ip) = client_ip(req) {
+        keys.push((LoginThrottle::ip_key(&ip), LOGIN_IP_LOCKOUT_THRESHOLD));
+    }
+    keys
+}
+
+/// 任一计数仍在退避或锁定期内时返回 429
+fn check_login_throttle(pool: &DbPool, req: &HttpRequest, username: &str, keys: &[(String, i32)]) -> Option<HttpResponse> {
+    let key_names: Vec<String> = keys.iter().map(|(key, _)| key.clone()).collect();
+    let throttles = match get_login_throttles(pool, &key_names) {
+        Ok(data) => data,
+        Err(err) => return Some(HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))),
+    };
+
+    let current = now();
+    let retry_after = throttles.iter().filter_map(|throttle| throttle.retry_after(current)).max()?;
+
+    audit::record(pool, req, audit::LOGIN_THROTTLED, None, Some(("username", username)), json!({"retry_after": retry_after}));
+
+    Some(HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(json!({"message": "尝试次数过多，请稍后再试", "retry_after": retry_after})))
 }
 
+fn login_failed(pool: &DbPool, req: &HttpRequest, username: &str, keys: &[(String, i32)]) -> HttpResponse {
+    for (key, threshold) in keys {
+        let _ = record_login_failure(pool, key, *threshold);
+    }
+    audit::record(pool, req, audit::LOGIN_FAILED, None, Some(("username", username)), json!({}));
+    HttpResponse::Unauthorized().json(json!({"message": "用户名或密码错误"}))
+}
 
 pub async fn handle_login(
+    req: HttpRequest,
     pool: web::Data<DbPool>,
     payload: web::Json<LoginPayload>,
 ) -> impl Responder {
-
+    let throttle_keys = login_throttle_keys(&req, &payload.username);
+    if let Some(response) = check_login_throttle(&pool, &req, &payload.username, &throttle_keys) {
+        return response;
+    }
 
     let user = match get_user_by_username(&pool, &payload.username) {
         Ok(user) => user,
-        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "用户不存在"})),
+        Err(diesel::result::Error::NotFound) => {
+            let _ = verify(&payload.password, dummy_password_hash());
+            return login_failed(&pool, &req, &payload.username, &throttle_keys);
+        }
         Err(err) => return HttpResponse::InternalServerError().json(err.to_string()),
     };
     
     
-    if verify(&payload.password, &user.password_hash).unwrap() {
-        let session_id = generate_uuid();
+    if verify(&payload.password, &user.password_hash).unwrap_or(false) {
+        complete_login(&pool, &req, &user, payload.device_name.clone())
+    }else{
+        login_failed(&pool, &req, &payload.username, &throttle_keys)
+    }
+
+}
+
+pub async fn handle_login_totp(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    payload: web::Json<TotpLoginPayload>,
+) -> impl Responder {
+    let (user_id, challenge_jti) = match decode_challenge_jwt(&payload.challenge_token) {
+        Ok(ids) => ids,
+        Err(_) => return HttpResponse::Unauthorized().json(json!({"message": "登录已超时，请重新输入密码"})),
+    };
+
+    let user = match get_user_by_id(&pool, user_id) {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::Unauthorized().json(json!({"message": "登录已超时，请重新输入密码"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    };
+
+    // 验证码同样计入该用户名的失败次数，防止在挑战令牌有效期内穷举
+    let throttle_keys = login_throttle_keys(&req, &user.username);
+    if let Some(response) = check_login_throttle(&pool, &req, &user.username, &throttle_keys) {
+        return response;
+    }
+
+    match verify_second_factor(&pool, &user, payload.code.as_deref(), payload.recovery_code.as_deref()) {
+        Ok(true) => {
+            let expires_at = now() + chrono::Duration::seconds(MFA_CHALLENGE_TTL_SECONDS);
+            match mark_mfa_challenge_used(&pool, challenge_jti, expires_at) {
+                Ok(0) => return HttpResponse::Unauthorized().json(json!({"message": "登录已超时，请重新输入密码"})),
+                Ok(_) => {}
+                Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+            }
+            if let Some(response) = inactive_account(&user) {
+                return response;
+            }
+            start_session(&pool, &req, &user, payload.device_name.clone())
+        }
+        Ok(false) => {
+            for (key, threshold) in &throttle_keys {
+                let _ = record_login_failure(&pool, key, *threshold);
+            }
+            audit::record(&pool, &req, audit::MFA_FAILED, Some(user.user_id), None, json!({}));
+            HttpResponse::Unauthorized().json(json!({"message": "验证码不正确"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+/// 校验 TOTP 验证码或恢复码，未启用两步验证的账号一律返回 false
+fn verify_second_factor(pool: &DbPool, user: &User, code: Option<&str>, recovery_code: Option<&str>) -> Result<bool, diesel::result::Error> {
+    let secret = match (&user.totp_secret, user.totp_enabled_at) {
+        (Some(secret), Some(_)) => secret,
+        _ => return Ok(false),
+    };
 
-        let jwt_token = match generate_jwt(&session_id) {
-            Ok(jwt_token) => jwt_token,
-            Err(err) => return HttpResponse::InternalServerError().json(err.to_string()), 
+    if let Some(code) = code {
+        return match verify_code(secret, code, Utc::now().timestamp()) {
+            Some(step) => Ok(mark_totp_step_used(pool, user.user_id, step)? == 1),
+            None => Ok(false),
         };
+    }
 
-        let new_session = NewSession::new(session_id, user.user_id, &jwt_token);
-        let _ = add_new_session(&pool, &new_session);
+    if let Some(recovery_code) = recovery_code {
+        let code_hash = hash_token(&normalize_recovery_code(recovery_code));
+        return Ok(consume_recovery_code(pool, user.user_id, &code_hash)? == 1);
+    }
 
-        let response_json = json!({
-            "token": jwt_token
-        });
-    
+    Ok(false)
+}
+
+/// 已停用或已申请注销的账号不能登录，注销宽限期内提示恢复方式
+fn inactive_account(user: &User) -> Option<HttpResponse> {
+    if user.disabled_at.is_some() {
+        return Some(HttpResponse::Forbidden().json(json!({"message": "账号已被停用，请联系管理员"})));
+    }
+    user.deletion_scheduled_at.map(|scheduled_at| HttpResponse::Forbidden().json(json!({
+        "message": "账号已申请注销，可在删除前通过 /v1/account/restore 恢复",
+        "deletion_scheduled_at": scheduled_at.to_string()
+    })))
+}
 
+/// 第一因素通过后的统一出口：开启了两步验证的账号先返回挑战令牌，验证码通过后才创建会话
+fn complete_login(pool: &DbPool, req: &HttpRequest, user: &User, device_name: Option<String>) -> HttpResponse {
+    if let Some(response) = inactive_account(user) {
+        return response;
+    }
 
-        HttpResponse::Ok()
-            .insert_header((header::AUTHORIZATION, format!("Bearer {}", jwt_token)))
-            .json(response_json)
-    }else{
-        HttpResponse::Unauthorized().json(json!({"message": "密码不正确"}))
+    if user.totp_enabled_at.is_some() {
+        return match generate_challenge_jwt(&user.user_id) {
+            Ok(challenge_token) => HttpResponse::Ok().json(json!({
+                "mfa_required": true,
+                "challenge_token": challenge_token,
+                "expires_in": MFA_CHALLENGE_TTL_SECONDS
+            })),
+            Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+        };
     }
 
+    start_session(pool, req, user, device_name)
 }
 
+/// 为通过认证的用户创建新的会话族并返回令牌。会话创建成功后才清除该用户名的失败计数，
+/// 只通过密码这一步不会重置计数，避免借重新登录无限次尝试验证码
+fn start_session(pool: &DbPool, req: &HttpRequest, user: &User, device_name: Option<String>) -> HttpResponse {
+    let user_id = user.user_id;
+    let client = client_info(req, device_name);
+    let (new_session, jwt_token, refresh_token) = match build_session(user_id, None, &client) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(err.to_string()), 
+    };
 
-pub async fn handle_logout(
-    req: HttpRequest,
+    if let Err(err) = add_new_session(pool, &new_session) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    let _ = clear_login_throttle(pool, &LoginThrottle::user_key(&user.username));
+
+    audit::record(pool, req, audit::LOGIN_SUCCEEDED, Some(user_id), Some(("session", &new_session.family_id.to_string())), json!({}));
+
+    token_response(&jwt_token, &refresh_token)
+}
+
+
+//  /v1/auth/oidc/providers
+pub async fn oidc_providers() -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "providers": oidc::provider_names(),
+        "status": "200",
+        "message": "查询登录方式成功"
+    }))
+}
+
+// 授权时把 state 写入只有本站能读取的 Cookie，回调时要求两者一致，
+// 防止攻击者把自己发起的登录请求塞给受害者完成（登录 CSRF）
+fn oidc_state_cookie(state: &str) -> Cookie<'static> {
+    Cookie::build(OIDC_STATE_COOKIE, state.to_string())
+        .path("/v1/auth/oidc")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(CookieDuration::minutes(OIDC_STATE_TTL_MINUTES))
+        .finish()
+}
+
+//  /v1/auth/oidc/{provider}/authorize，返回身份提供方的授权地址，由前端负责跳转
+pub async fn oidc_authorize(
+    provider: web::Path<String>,
     pool: web::Data<DbPool>,
 ) -> impl Responder {
-    let auth_header = req.headers().get("Authorization");
+    let provider = match oidc::get_provider(&provider.into_inner()) {
+        Some(provider) => provider,
+        None => return HttpResponse::NotFound().json(json!({"message": "不支持的登录方式"})),
+    };
 
-    let token = match auth_header.and_then(|h| h.to_str().ok()) {
-        Some(t) => t.to_string(),
-        None => return HttpResponse::Unauthorized().json(json!({"message": "用户未登录"})),
+    let state = generate_token();
+    let nonce = generate_token();
+    let code_verifier = generate_token();
+
+    let authorization_url = match provider.authorization_url(&state, &nonce, &code_verifier).await {
+        Ok(url) => url,
+        Err(err) => return HttpResponse::BadGateway().json(json!({"message": err})),
     };
 
-    let session_uuid = match decode_jwt(&token) {
-        Ok(uuid) => uuid,
-        Err(_) => return HttpResponse::Unauthorized().json(json!({"message": "用户未登录"})),
+    let login_state = OidcLoginState::new(&state, &provider.name, &code_verifier, &nonce);
+    if let Err(err) = add_oidc_login_state(&pool, &login_state) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    HttpResponse::Ok()
+        .cookie(oidc_state_cookie(&state))
+        .json(json!({
+            "authorization_url": authorization_url,
+            "state": state
+        }))
+}
+
+//  /v1/auth/oidc/{provider}/callback，前端把回调得到的 code 与 state 提交到这里，签发与密码登录相同的会话。
+//  请求须带上授权时下发的 state Cookie
+pub async fn oidc_callback(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    pool: web::Data<DbPool>,
+    payload: web::Json<OidcCallbackPayload>,
+) -> impl Responder {
+    let provider = match oidc::get_provider(&provider.into_inner()) {
+        Some(provider) => provider,
+        None => return HttpResponse::NotFound().json(json!({"message": "不支持的登录方式"})),
     };
 
-    let _ = match get_session_by_session_id(&pool, session_uuid) {
-        Ok(session) => session,
-        Err(_) => return HttpResponse::Unauthorized().json(json!({"message": "用户未登录"})),
+    match req.cookie(OIDC_STATE_COOKIE) {
+        Some(cookie) if cookie.value() == payload.state => {}
+        _ => return HttpResponse::BadRequest().json(json!({"message": "登录请求无效或已过期"})),
+    }
+
+    let login_state = match take_oidc_login_state(&pool, &payload.state, &provider.name) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::BadRequest().json(json!({"message": "登录请求无效或已过期"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
     };
 
-    let response = match update_expries_by_session(&pool, session_uuid, now()) {
-        Ok(_) => HttpResponse::Ok().json(json!({"message": "已成功退出登录"})),
-        Err(_) => HttpResponse::Unauthorized().json(json!({"message": "登出失败"}))
+    let claims = match provider.exchange_code(&payload.code, &login_state.code_verifier, &login_state.nonce).await {
+        Ok(claims) => claims,
+        Err(err) => return HttpResponse::Unauthorized().json(json!({"message": format!("第三方登录失败: {}", err)})),
     };
+
+    let user = match get_user_by_identity(&pool, &provider.name, &claims.sub) {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => match link_or_create_oidc_user(&pool, &provider.name, &claims) {
+            Ok(user) => user,
+            Err(response) => return response,
+        },
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    };
+
+    let mut response = complete_login(&pool, &req, &user, payload.device_name.clone());
+    let _ = response.add_removal_cookie(&oidc_state_cookie(""));
     response
 }
 
+/// 首次使用外部身份登录：双方都确认过的同一邮箱关联到已有账号，否则新建账号
+fn link_or_create_oidc_user(pool: &DbPool, provider: &str, claims: &oidc::IdTokenClaims) -> Result<User, HttpResponse> {
+    let internal_error = |err: diesel::result::Error| HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
 
-pub async fn handle_register(
-    pool: web::Data<DbPool>,
-    payload: web::Json<RegisterPayload>,
-) -> impl Responder {
-    let user = get_user_by_username(&pool, &payload.username);
+    let email = match claims.email.as_deref().map(|e| e.trim()).filter(|e| is_valid_email(e)) {
+        Some(email) => email,
+        None => return Err(HttpResponse::BadRequest().json(json!({"message": "身份提供方未返回有效邮箱"}))),
+    };
+    let email_verified = claims.email_verified.unwrap_or(false);
 
-    if !matches!(user, Err(diesel::result::Error::NotFound)) {
-        return HttpResponse::Conflict().json(json!({"message": "用户名已被注册"}));
+    match get_user_by_email(pool, email) {
+        Ok(existing) => {
+            if !email_verified || existing.email_verified_at.is_none() {
+                return Err(HttpResponse::Conflict().json(json!({"message": "该邮箱已被其他账号使用，请先使用密码登录"})));
+            }
+            add_user_identity(pool, &NewUserIdentity::new(existing.user_id, provider, &claims.sub, Some(email)))
+                .map_err(internal_error)?;
+            return Ok(existing);
+        }
+        Err(diesel::result::Error::NotFound) => {}
+        Err(err) => return Err(internal_error(err)),
     }
 
-    let new_user = NewUser::new(&payload.username, &payload.email, &payload.password);
+    let base: String = claims.preferred_username.as_deref()
+        .or(claims.name.as_deref())
+        .or(email.split('@').next())
+        .unwrap_or("user")
+        .chars()
+        .filter(|c| c.is_alphanumeric() || "_-.".contains(*c))
+        .take(40)
+        .collect();
+    let base = if base.is_empty() { String::from("user") } else { base };
 
-    if let Err(err) = add_new_user(&pool, &new_user) {
-        HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
-    }else{
-        HttpResponse::Ok().json(json!({"message": "注册成功"}))
-    }  
+    let mut username = base.clone();
+    for _ in 0..5 {
+        match get_user_by_username(pool, &username) {
+            Err(diesel::result::Error::NotFound) => break,
+            Ok(_) => {
+                let suffix: String = generate_token().chars().filter(|c| c.is_ascii_alphanumeric()).take(6).collect();
+                username = format!("{}_{}", base, suffix);
+            }
+            Err(err) => return Err(internal_error(err)),
+        }
+    }
+
+    // 外部身份登录的账号没有可用的本地密码，需要时可以通过找回密码设置
+    let mut new_user = NewUser::new(&username, email, &generate_token());
+    if email_verified {
+        new_user = new_user.with_verified_email();
+    }
+    let new_identity = NewUserIdentity::new(new_user.user_id, provider, &claims.sub, Some(email));
+
+    match add_user_with_identity(pool, &new_user, &new_identity) {
+        Ok(_) => {}
+        Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+            return Err(HttpResponse::Conflict().json(json!({"message": "账号创建冲突，请重试"})));
+        }
+        Err(err) => return Err(internal_error(err)),
+    }
+
+    get_user_by_id(pool, new_user.user_id).map_err(internal_error)
 }
 
-pub async fn chat_new(
+
+pub async fn handle_refresh(
     req: HttpRequest,
     pool: web::Data<DbPool>,
-    payload: web::Json<NewChatPayload>, 
+    payload: web::Json<RefreshPayload>,
 ) -> impl Responder {
-    let is_auth = req.extensions().get::<bool>().unwrap().clone();
-    if !is_auth{
-        return HttpResponse::Unauthorized().json(json!({"message": "用户未登录"}));
+    let session = match get_session_by_refresh_hash(&pool, &hash_token(&payload.refresh_token)) {
+        Ok(session) => session,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::Unauthorized().json(json!({"message": "刷新令牌无效"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    };
+
+    // 已轮换过的刷新令牌再次出现，说明令牌可能已泄露，整个会话族一并吊销
+    if session.rotated_at.is_some() {
+        let _ = revoke_session_family(&pool, session.family_id, now());
+        audit::record(&pool, &req, audit::REFRESH_REUSED, Some(session.user_id), Some(("session", &session.family_id.to_string())), json!({}));
+        return HttpResponse::Unauthorized().json(json!({"message": "刷新令牌已被使用，请重新登录"}));
     }
 
-    if payload.title.len() < 3 {
-        return HttpResponse::BadRequest().json(json!({"message": "标题过短"}));
+    if session.expires_at < now() {
+        return HttpResponse::Unauthorized().json(json!({"message": "会话已过期，请重新登录"}));
     }
 
-    let session = req.extensions().get::<Session>().unwrap().clone();
-    let new_chat = NewChat::new(session.user_id, &payload.title);
+    let client = client_info(&req, session.device_name.clone());
+    let (new_session, jwt_token, refresh_token) = match build_session(session.user_id, Some(session.family_id), &client) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    };
 
-    if let Err(err) = add_new_chat(&pool, &new_chat){
-        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
-    }else{
-        HttpResponse::Ok().json(json!({"message": "创建对话成功", "chat_id": new_chat.chat_id.to_string()}))
+    match rotate_session(&pool, session.session_id, &new_session) {
+        Ok(true) => token_response(&jwt_token, &refresh_token),
+        Ok(false) => {
+            let _ = revoke_session_family(&pool, session.family_id, now());
+            audit::record(&pool, &req, audit::REFRESH_REUSED, Some(session.user_id), Some(("session", &session.family_id.to_string())), json!({}));
+            HttpResponse::Unauthorized().json(json!({"message": "刷新令牌已被使用，请重新登录"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
     }
 }
 
-pub async fn chat_history(
+/// 生成新会话及其访问令牌、刷新令牌。`family_id` 为空时开启新的会话族
+fn build_session(user_id: Uuid, family_id: Option<Uuid>, client: &ClientInfo) -> Result<(NewSession, String, String), jsonwebtoken::errors::Error> {
+    let session_id = generate_uuid();
+    let jwt_token = generate_jwt(&session_id, &user_id)?;
+    let refresh_token = generate_token();
+
+    let new_session = NewSession::new(
+        session_id,
+        user_id,
+        &jwt_token,
+        family_id.unwrap_or(session_id),
+        &hash_token(&refresh_token),
+    ).with_client_info(client);
+    Ok((new_session, jwt_token, refresh_token))
+}
+
+/// 客户端 IP。默认取直连地址；直连地址是 `TRUSTED_PROXIES` 中的代理时，从 X-Forwarded-For 右侧
+/// 向左跳过可信代理，取第一个不可信的地址。客户端自己填写的部分不会被采信
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let mut ip = req.peer_addr()?.ip();
+    let proxies = trusted_proxies();
+
+    if proxies.contains(&ip) {
+        let forwarded = req.headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .map(|hop| hop.trim().to_string())
+            .collect::<Vec<String>>();
+
+        for hop in forwarded.iter().rev() {
+            match hop.parse::<IpAddr>() {
+                Ok(hop_ip) => {
+                    ip = hop_ip;
+                    if !proxies.contains(&hop_ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    Some(ip.to_string())
+}
+
+pub fn client_info(req: &HttpRequest, device_name: Option<String>) -> ClientInfo {
+    ClientInfo {
+        device_name: device_name.map(|name| name.chars().take(100).collect()),
+        ip_address: client_ip(req),
+        user_agent: req.headers()
+            .get(header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|ua| ua.to_string()),
+    }
+}
+
+fn token_response(jwt_token: &str, refresh_token: &str) -> HttpResponse {
+    let response_json = json!({
+        "token": jwt_token,
+        "refresh_token": refresh_token,
+        "expires_in": ACCESS_TOKEN_TTL_SECONDS
+    });
+
+    HttpResponse::Ok()
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", jwt_token)))
+        .json(response_json)
+}
+
+
+pub async fn handle_logout(
     req: HttpRequest,
+    user: SessionUser,
     pool: web::Data<DbPool>,
 ) -> impl Responder {
-    let is_auth = req.extensions().get::<bool>().unwrap().clone();
-    if !is_auth{
-        return HttpResponse::Unauthorized().json(json!({"message": "用户未登录"}));
+    // 同一会话族共享同一个刷新令牌链，登出时一并失效
+    match revoke_session_family(&pool, user.session.family_id, now()) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::LOGOUT, Some(user.user_id), Some(("session", &user.session.family_id.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "已成功退出登录"}))
+        }
+        Err(_) => HttpResponse::Unauthorized().json(json!({"message": "登出失败"}))
     }
-    let session = req.extensions().get::<Session>().unwrap().clone();
+}
+
+
+fn session_json(session: &Session, current_family: Uuid) -> Value {
+    json!({
+        "session_id": session.family_id.to_string(),
+        "device_name": session.device_name,
+        "ip_address": session.ip_address,
+        "user_agent": session.user_agent,
+        "last_seen_at": session.last_seen_at.map(|t| t.to_string()),
+        "expires_at": session.expires_at.to_string(),
+        "current": session.family_id == current_family
+    })
+}
 
-    let chats = match get_all_chats_by_user_id(&pool, session.user_id){
+//  /v1/auth/sessions，会话 ID 对外使用 family_id，刷新令牌轮换后保持不变
+pub async fn session_list(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let sessions = match get_active_sessions_by_user_id(&pool, user.user_id) {
         Ok(data) => data,
         Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
     };
 
-    let mut chats_fliter: Vec<Value> = vec![];
-
-    for chat in chats {
-        let chat_json = json!({
-            "chat_id": chat.chat_id.to_string(),
-            "title": chat.title,
-            "created_at": chat.created_at.unwrap().to_string()
-        });
-        chats_fliter.push(chat_json);
-    }
+    let sessions_json: Vec<Value> = sessions.iter()
+        .map(|session| session_json(session, user.session.family_id))
+        .collect();
 
-    let response = json!({
-        "chats": chats_fliter,
+    HttpResponse::Ok().json(json!({
+        "sessions": sessions_json,
         "status": "200",
-        "message": "查询历史对话成功"
-    });
-
-    HttpResponse::Ok().json(response)
+        "message": "查询登录会话成功"
+    }))
 }
 
-//  /v1/chat/{chat_id}
-pub async fn chat_content(
-    chat_id: web::Path<String>,
-    pool: Data<DbPool>,
-) -> impl Responder{
-
-    let chat_uuid = match Uuid::from_str(&chat_id.into_inner()) {
+pub async fn session_detail(
+    user: SessionUser,
+    session_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let family_uuid = match Uuid::from_str(&session_id.into_inner()) {
         Ok(data) => data,
         Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
     };
 
-    let msgs = match get_all_messages_by_chat_id(&pool, chat_uuid) {
+    let sessions = match get_active_sessions_by_user_id(&pool, user.user_id) {
         Ok(data) => data,
         Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
     };
 
-    let mut msgs_json: Vec<Value> = vec![];
-    
-    for msg in msgs{
-        let msg_json = json!({
-            "role": msg.role,
-            "content": msg.content,
-            "timestamp": msg.timestamp.unwrap().to_string()
-        });
-        msgs_json.push(msg_json);
+    match sessions.iter().find(|session| session.family_id == family_uuid) {
+        Some(session) => HttpResponse::Ok().json(json!({
+            "session": session_json(session, user.session.family_id),
+            "status": "200",
+            "message": "查询登录会话成功"
+        })),
+        None => HttpResponse::NotFound().json(json!({"message": "会话不存在"})),
     }
-
-    let response = json!({
-        "chats": msgs_json,
-        "status": "200",
-        "message": "查询对话内容成功"
-    });
-
-    HttpResponse::Ok().json(json!(response))
 }
 
-pub async fn chat_delete(
-    chat_id: web::Path<String>,
-    pool: Data<DbPool>,
+pub async fn session_revoke(
+    req: HttpRequest,
+    user: SessionUser,
+    session_id: web::Path<String>,
+    pool: web::Data<DbPool>,
 ) -> impl Responder {
-    let chat_uuid = match Uuid::from_str(&chat_id.into_inner()) {
+    let family_uuid = match Uuid::from_str(&session_id.into_inner()) {
         Ok(data) => data,
-        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})),
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
     };
 
-    match delete_chat(&pool, chat_uuid) {
-        Ok(_) => HttpResponse::Ok().json(json!({"message": "对话删除成功"})),
-        Err(_) => HttpResponse::InternalServerError().json(json!({"message": "删除失败"})),
+    let sessions = match get_active_sessions_by_user_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if !sessions.iter().any(|session| session.family_id == family_uuid) {
+        return HttpResponse::NotFound().json(json!({"message": "会话不存在"}));
+    }
+
+    match revoke_session_family(&pool, family_uuid, now()) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::SESSION_REVOKED, Some(user.user_id), Some(("session", &family_uuid.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "会话已注销"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
     }
 }
 
-pub async fn proxy_stream(
-    req_body: web::Json<ChatPayload>,
-    pool: Data<DbPool>
+//  默认保留当前会话，include_current=true 时连同当前会话一起注销
+pub async fn session_revoke_all(
+    req: HttpRequest,
+    user: SessionUser,
+    query: web::Query<RevokeSessionsQuery>,
+    pool: web::Data<DbPool>,
 ) -> impl Responder {
-    let client = Client::new();
-    let url = "http://localhost:8000/stream";
+    let except_family = if query.include_current { None } else { Some(user.session.family_id) };
 
-    let prompt = req_body.prompt.clone();
-    let chat_id = Uuid::from_str(&req_body.chat_id).unwrap();
+    match revoke_all_sessions_by_user_id(&pool, user.user_id, except_family, now()) {
+        Ok(count) => {
+            audit::record(&pool, &req, audit::SESSIONS_REVOKED, Some(user.user_id), None, json!({"include_current": query.include_current, "count": count}));
+            HttpResponse::Ok().json(json!({"message": "会话已注销"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
 
-    let user_msg: NewMessage = NewMessage::new(chat_id, &String::from("user"), &prompt);
-    
-    let _ = add_new_message(&pool, &user_msg);
 
+pub async fn password_change(
+    req: HttpRequest,
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<ChangePasswordPayload>,
+) -> impl Responder {
+    if payload.new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return HttpResponse::BadRequest().json(json!({"message": "新密码过短"}));
+    }
 
-    let res = match client.post(url).json(&req_body).send().await {
-        Ok(res) => res,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to connect to backend"),
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
     };
 
-    let (tx, mut rx) = mpsc::channel::<Bytes>(10);
-    let mut collected_response = Vec::new();
+    if !verify(&payload.old_password, &account.password_hash).unwrap_or(false) {
+        return HttpResponse::Unauthorized().json(json!({"message": "原密码不正确"}));
+    }
 
-    let mut stream = res.bytes_stream();
+    let new_hash = match hash_password(&payload.new_password) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
 
-    tokio::spawn(async move {
-        while let Some(chunk) = stream.try_next().await.unwrap_or(None) {
-            collected_response.extend_from_slice(&chunk);
+    if let Err(err) = update_password_hash(&pool, user.user_id, &new_hash) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
 
-            if tx.send(Bytes::from(chunk)).await.is_err() {
-                break;
-            }
-        }
+    // 修改密码后其他设备需要重新登录，当前会话保留
+    let _ = revoke_all_sessions_by_user_id(&pool, user.user_id, Some(user.session.family_id), now());
 
-        // println!("{}", String::from_utf8_lossy(&collected_response));
-        let json_str = String::from_utf8_lossy(&collected_response).into_owned();
-        let parsed: Value = serde_json::from_str(&json_str).unwrap();
+    audit::record(&pool, &req, audit::PASSWORD_CHANGED, Some(user.user_id), None, json!({}));
 
-        let result: String = parsed["response"]
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|item| item["chunk"].as_str().unwrap_or(""))
-            .collect();
+    HttpResponse::Ok().json(json!({"message": "密码修改成功"}))
+}
 
-        let ai_msg: NewMessage = NewMessage::new(chat_id, &String::from("assistant"), &result);
-        let _ = add_new_message(&pool, &ai_msg);
+/// 生成重置令牌并发送重置邮件，邮件发送失败只记录日志
+fn send_password_reset_email(pool: &DbPool, mailer: &dyn Mailer, account: &User) -> Result<(), diesel::result::Error> {
+    let reset_token = generate_token();
+    let new_token = NewPasswordResetToken::new(account.user_id, &hash_token(&reset_token));
+    add_password_reset_token(pool, &new_token)?;
 
-        println!("{}", result);
-    });
+    let reset_url = env::var("PASSWORD_RESET_URL")
+        .unwrap_or_else(|_| String::from("http://localhost:3000/reset-password"));
+    let body = format!(
+        "{}，你好：\n\n请在 {} 分钟内打开以下链接重置密码：\n{}?token={}\n\n如果这不是你本人的操作，请忽略这封邮件。",
+        account.username, PASSWORD_RESET_TTL_MINUTES, reset_url, reset_token
+    );
 
-    let response_stream = async_stream::stream! {
-        while let Some(chunk) = rx.recv().await {
-            yield Ok::<Bytes, actix_web::Error>(chunk);
-        }
-    };
+    if let Err(err) = mailer.send(&account.email, "重置密码", &body) {
+        println!("重置密码邮件发送失败: {}", err);
+    }
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .streaming(response_stream)
+    Ok(())
 }
 
-pub async fn ocr_handle(
+//  无论邮箱是否注册都返回相同的结果，避免被用来探测账号
+pub async fn password_forgot(
     req: HttpRequest,
-    payload: web::Json<OCRPalyload>,
-) -> impl Responder{
-    let is_auth = req.extensions().get::<bool>().unwrap().clone();
-    if !is_auth{
-        return HttpResponse::Unauthorized().json(json!({"message": "用户未登录"}));
-    }
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    payload: web::Json<ForgotPasswordPayload>,
+) -> impl Responder {
+    let response = HttpResponse::Ok().json(json!({"message": "如果该邮箱已注册，重置邮件已发送"}));
+
+    let account = match get_user_by_email(&pool, payload.email.trim()) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return response,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if let Err(err) = send_password_reset_email(&pool, mailer.get_ref(), &account) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    audit::record(&pool, &req, audit::PASSWORD_RESET_REQUESTED, Some(account.user_id), None, json!({}));
+
+    response
+}
+
+pub async fn password_reset(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    payload: web::Json<ResetPasswordPayload>,
+) -> impl Responder {
+    if payload.new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return HttpResponse::BadRequest().json(json!({"message": "新密码过短"}));
+    }
+
+    let new_hash = match hash_password(&payload.new_password) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    match reset_password_with_token(&pool, &hash_token(&payload.token), &new_hash) {
+        Ok(user_id) => {
+            audit::record(&pool, &req, audit::PASSWORD_RESET, Some(user_id), None, json!({}));
+            HttpResponse::Ok().json(json!({"message": "密码重置成功，请重新登录"}))
+        }
+        Err(diesel::result::Error::NotFound) => HttpResponse::BadRequest().json(json!({"message": "重置链接无效或已过期"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+
+fn new_recovery_codes(user_id: Uuid) -> (Vec<String>, Vec<NewRecoveryCode>) {
+    let codes = generate_recovery_codes(RECOVERY_CODE_COUNT);
+    let records = codes.iter()
+        .map(|code| NewRecoveryCode::new(user_id, &hash_token(code)))
+        .collect();
+    (codes, records)
+}
+
+//  /v1/auth/totp/setup，生成待确认的密钥，确认前不影响登录
+pub async fn totp_setup(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if account.totp_enabled_at.is_some() {
+        return HttpResponse::Conflict().json(json!({"message": "已开启两步验证"}));
+    }
+
+    let secret = generate_totp_secret();
+    if let Err(err) = set_pending_totp_secret(&pool, user.user_id, &secret) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    HttpResponse::Ok().json(json!({
+        "secret": secret,
+        "otpauth_uri": otpauth_uri(&account.username, &secret),
+        "message": "请使用验证器扫描二维码后提交验证码"
+    }))
+}
+
+pub async fn totp_confirm(
+    req: HttpRequest,
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<TotpCodePayload>,
+) -> impl Responder {
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if account.totp_enabled_at.is_some() {
+        return HttpResponse::Conflict().json(json!({"message": "已开启两步验证"}));
+    }
+
+    let secret = match &account.totp_secret {
+        Some(secret) => secret,
+        None => return HttpResponse::BadRequest().json(json!({"message": "请先生成两步验证密钥"})),
+    };
+
+    let step = match verify_code(secret, &payload.code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return HttpResponse::BadRequest().json(json!({"message": "验证码不正确"})),
+    };
+
+    let (codes, records) = new_recovery_codes(user.user_id);
+    if let Err(err) = enable_totp(&pool, user.user_id, step, &records) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    audit::record(&pool, &req, audit::TOTP_ENABLED, Some(user.user_id), None, json!({}));
+
+    HttpResponse::Ok().json(json!({
+        "recovery_codes": codes,
+        "message": "两步验证已开启，请妥善保存恢复码"
+    }))
+}
+
+pub async fn totp_recovery_codes(
+    req: HttpRequest,
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<TotpCodePayload>,
+) -> impl Responder {
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    match verify_second_factor(&pool, &account, Some(&payload.code), None) {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().json(json!({"message": "验证码不正确"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+
+    let (codes, records) = new_recovery_codes(user.user_id);
+    if let Err(err) = replace_recovery_codes(&pool, user.user_id, &records) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    audit::record(&pool, &req, audit::RECOVERY_CODES_REGENERATED, Some(user.user_id), None, json!({}));
+
+    HttpResponse::Ok().json(json!({
+        "recovery_codes": codes,
+        "message": "恢复码已重新生成，旧恢复码已失效"
+    }))
+}
+
+pub async fn totp_disable(
+    req: HttpRequest,
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<TotpDisablePayload>,
+) -> impl Responder {
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if !verify(&payload.password, &account.password_hash).unwrap_or(false) {
+        return HttpResponse::Unauthorized().json(json!({"message": "密码不正确"}));
+    }
+
+    match verify_second_factor(&pool, &account, payload.code.as_deref(), payload.recovery_code.as_deref()) {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().json(json!({"message": "验证码不正确"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+
+    match disable_totp(&pool, user.user_id) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::TOTP_DISABLED, Some(user.user_id), None, json!({}));
+            HttpResponse::Ok().json(json!({"message": "两步验证已关闭"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+
+fn admin_user_json(account: &User) -> Value {
+    json!({
+        "user_id": account.user_id.to_string(),
+        "username": account.username,
+        "email": account.email,
+        "role": account.role,
+        "email_verified": account.email_verified_at.is_some(),
+        "totp_enabled": account.totp_enabled_at.is_some(),
+        "created_at": account.created_at.map(|t| t.to_string()),
+        "disabled_at": account.disabled_at.map(|t| t.to_string()),
+        "deletion_scheduled_at": account.deletion_scheduled_at.map(|t| t.to_string())
+    })
+}
+
+fn parse_user_id(user_id: &str) -> Result<Uuid, HttpResponse> {
+    Uuid::parse_str(user_id).map_err(|_| HttpResponse::BadRequest().json(json!({"message": "无效的用户 ID"})))
+}
+
+fn find_user(pool: &DbPool, user_id: &str) -> Result<User, HttpResponse> {
+    let user_uuid = parse_user_id(user_id)?;
+    get_user_by_id(pool, user_uuid).map_err(|err| match err {
+        diesel::result::Error::NotFound => HttpResponse::NotFound().json(json!({"message": "用户不存在"})),
+        err => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    })
+}
+
+//  /v1/admin/users?q=&role=&limit=&offset=
+pub async fn admin_user_list(
+    query: web::Query<AdminUserListQuery>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let keyword = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+
+    if let Some(role) = &query.role {
+        if Role::from_str(role).is_err() {
+            return HttpResponse::BadRequest().json(json!({"message": "未知的角色"}));
+        }
+    }
+
+    let (accounts, total) = match list_users(&pool, keyword, query.role.as_deref(), limit, offset) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let users_json: Vec<Value> = accounts.iter().map(admin_user_json).collect();
+
+    HttpResponse::Ok().json(json!({
+        "users": users_json,
+        "total": total,
+        "status": "200",
+        "message": "查询用户成功"
+    }))
+}
+
+//  /v1/admin/users/{user_id}
+pub async fn admin_user_detail(
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    match find_user(&pool, &user_id) {
+        Ok(account) => HttpResponse::Ok().json(admin_user_json(&account)),
+        Err(response) => response,
+    }
+}
+
+//  /v1/admin/users/{user_id}/disable，停用账号并注销其所有会话
+pub async fn admin_user_disable(
+    req: HttpRequest,
+    admin: SessionUser,
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let account = match find_user(&pool, &user_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    if account.user_id == admin.user_id {
+        return HttpResponse::BadRequest().json(json!({"message": "不能停用自己的账号"}));
+    }
+
+    match set_user_disabled(&pool, account.user_id, Some(now())) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::ADMIN_USER_DISABLED, Some(admin.user_id), Some(("user", &account.user_id.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "账号已停用"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/admin/users/{user_id}/enable
+pub async fn admin_user_enable(
+    req: HttpRequest,
+    admin: SessionUser,
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let account = match find_user(&pool, &user_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match set_user_disabled(&pool, account.user_id, None) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::ADMIN_USER_ENABLED, Some(admin.user_id), Some(("user", &account.user_id.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "账号已恢复"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/admin/users/{user_id}/role
+pub async fn admin_user_role(
+    req: HttpRequest,
+    admin: SessionUser,
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+    payload: web::Json<AdminRolePayload>,
+) -> impl Responder {
+    let role = match Role::from_str(&payload.role) {
+        Ok(role) => role,
+        Err(err) => return HttpResponse::BadRequest().json(json!({"message": err})),
+    };
+
+    let account = match find_user(&pool, &user_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    // 防止最后一个管理员把自己降级后无人可以管理
+    if account.user_id == admin.user_id && role != admin.role {
+        return HttpResponse::BadRequest().json(json!({"message": "不能修改自己的角色"}));
+    }
+
+    match update_user_role(&pool, account.user_id, role.as_str()) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::ADMIN_ROLE_CHANGED, Some(admin.user_id), Some(("user", &account.user_id.to_string())), json!({"from": account.role, "to": role.as_str()}));
+            HttpResponse::Ok().json(json!({"message": "角色已更新", "role": role.as_str()}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/admin/users/{user_id}/password，直接设置新密码或向用户发送重置邮件
+pub async fn admin_user_password_reset(
+    req: HttpRequest,
+    admin: SessionUser,
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    payload: web::Json<AdminPasswordResetPayload>,
+) -> impl Responder {
+    let account = match find_user(&pool, &user_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let new_password = match &payload.new_password {
+        Some(new_password) => new_password,
+        None => {
+            return match send_password_reset_email(&pool, mailer.get_ref(), &account) {
+                Ok(_) => {
+                    audit::record(&pool, &req, audit::ADMIN_PASSWORD_RESET, Some(admin.user_id), Some(("user", &account.user_id.to_string())), json!({"method": "email"}));
+                    HttpResponse::Ok().json(json!({"message": "重置邮件已发送"}))
+                }
+                Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+            };
+        }
+    };
+
+    if new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return HttpResponse::BadRequest().json(json!({"message": "新密码过短"}));
+    }
+
+    let new_hash = match hash_password(new_password) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if let Err(err) = update_password_hash(&pool, account.user_id, &new_hash) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    let _ = revoke_all_sessions_by_user_id(&pool, account.user_id, None, now());
+
+    audit::record(&pool, &req, audit::ADMIN_PASSWORD_RESET, Some(admin.user_id), Some(("user", &account.user_id.to_string())), json!({"method": "direct"}));
+
+    HttpResponse::Ok().json(json!({"message": "密码已重置"}))
+}
+
+//  /v1/admin/users/{user_id}/unlock，清除该用户的登录失败计数与锁定
+pub async fn admin_unlock_user(
+    req: HttpRequest,
+    admin: SessionUser,
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let account = match find_user(&pool, &user_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match clear_login_throttle(&pool, &LoginThrottle::user_key(&account.username)) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::ADMIN_USER_UNLOCKED, Some(admin.user_id), Some(("user", &account.user_id.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "账号已解锁"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/admin/users/{user_id}/usage
+pub async fn admin_user_usage(
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let account = match find_user(&pool, &user_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match get_usage_stats(&pool, Some(account.user_id), now() - chrono::Duration::days(USAGE_RECENT_DAYS)) {
+        Ok(stats) => HttpResponse::Ok().json(json!({
+            "user_id": account.user_id.to_string(),
+            "usage": stats,
+            "recent_days": USAGE_RECENT_DAYS
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/admin/usage，全站使用量
+pub async fn admin_usage(
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    match get_usage_stats(&pool, None, now() - chrono::Duration::days(USAGE_RECENT_DAYS)) {
+        Ok(stats) => HttpResponse::Ok().json(json!({
+            "usage": stats,
+            "recent_days": USAGE_RECENT_DAYS
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+
+//  /v1/admin/audit-events?user_id=&event_type=&from=&to=&limit=
+pub async fn admin_audit_events(
+    query: web::Query<AuditQuery>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let actor = match query.user_id.as_deref().map(Uuid::from_str) {
+        Some(Ok(uuid)) => Some(uuid),
+        Some(Err(_)) => return HttpResponse::BadRequest().json(json!({"message": "无效的用户 ID"})),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+
+    let events = match query_audit_events(&pool, actor, query.event_type.as_deref(), query.from, query.to, limit) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let events_json: Vec<Value> = events.iter().map(|event| json!({
+        "event_id": event.event_id.to_string(),
+        "event_type": event.event_type,
+        "actor_id": event.actor_id.map(|id| id.to_string()),
+        "target_type": event.target_type,
+        "target_id": event.target_id,
+        "ip_address": event.ip_address,
+        "user_agent": event.user_agent,
+        "details": event.details,
+        "created_at": event.created_at.to_string()
+    })).collect();
+
+    HttpResponse::Ok().json(json!({
+        "events": events_json,
+        "status": "200",
+        "message": "查询审计日志成功"
+    }))
+}
+
+
+fn api_key_json(api_key: &ApiKey) -> Value {
+    json!({
+        "api_key_id": api_key.api_key_id.to_string(),
+        "name": api_key.name,
+        "prefix": format!("{}{}", API_KEY_PREFIX, api_key.key_prefix),
+        "scopes": api_key.scope_list(),
+        "created_at": api_key.created_at.map(|t| t.to_string()),
+        "last_used_at": api_key.last_used_at.map(|t| t.to_string()),
+        "expires_at": api_key.expires_at.map(|t| t.to_string())
+    })
+}
+
+//  /v1/auth/api-keys
+pub async fn api_key_list(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let api_keys = match get_api_keys_by_user_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let keys_json: Vec<Value> = api_keys.iter().map(api_key_json).collect();
+
+    HttpResponse::Ok().json(json!({
+        "api_keys": keys_json,
+        "status": "200",
+        "message": "查询 API 密钥成功"
+    }))
+}
+
+//  完整密钥只在创建时返回一次，服务端只保存哈希
+pub async fn api_key_new(
+    req: HttpRequest,
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<NewApiKeyPayload>,
+) -> impl Responder {
+    let name = payload.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return HttpResponse::BadRequest().json(json!({"message": "密钥名称长度需在 1 到 100 个字符之间"}));
+    }
+
+    if payload.scopes.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"message": "至少需要一个权限范围"}));
+    }
+    if let Some(scope) = payload.scopes.iter().find(|scope| !API_KEY_SCOPES.contains(&scope.as_str())) {
+        return HttpResponse::BadRequest().json(json!({"message": format!("未知的权限范围: {}", scope)}));
+    }
+
+    let expires_at = match payload.expires_in_days {
+        Some(days) if days <= 0 => return HttpResponse::BadRequest().json(json!({"message": "有效期必须大于 0 天"})),
+        Some(days) => now().checked_add_signed(chrono::Duration::days(days)),
+        None => None,
+    };
+
+    let mut scopes = payload.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    let (api_key, full_key) = ApiKey::generate(user.user_id, name, &scopes, expires_at);
+    if let Err(err) = add_api_key(&pool, &api_key) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    audit::record(&pool, &req, audit::API_KEY_CREATED, Some(user.user_id), Some(("api_key", &api_key.api_key_id.to_string())), json!({"scopes": scopes}));
+
+    HttpResponse::Ok().json(json!({
+        "api_key": api_key_json(&api_key),
+        "key": full_key,
+        "message": "API 密钥已创建，请立即保存，之后将无法再次查看"
+    }))
+}
+
+pub async fn api_key_revoke(
+    req: HttpRequest,
+    user: SessionUser,
+    api_key_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let key_uuid = match Uuid::from_str(&api_key_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    match revoke_api_key(&pool, key_uuid, user.user_id) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "API 密钥不存在"})),
+        Ok(_) => {
+            audit::record(&pool, &req, audit::API_KEY_REVOKED, Some(user.user_id), Some(("api_key", &key_uuid.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "API 密钥已吊销"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+
+pub async fn handle_register(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    payload: web::Json<RegisterPayload>,
+) -> impl Responder {
+    let email = payload.email.trim();
+    if !is_valid_email(email) {
+        return HttpResponse::BadRequest().json(json!({"message": "邮箱格式不正确"}));
+    }
+
+    let user = get_user_by_username(&pool, &payload.username);
+
+    if !matches!(user, Err(diesel::result::Error::NotFound)) {
+        return HttpResponse::Conflict().json(json!({"message": "用户名已被注册"}));
+    }
+
+    if !matches!(get_user_by_email(&pool, email), Err(diesel::result::Error::NotFound)) {
+        return HttpResponse::Conflict().json(json!({"message": "邮箱已被注册"}));
+    }
+
+    let new_user = NewUser::new(&payload.username, email, &payload.password);
+
+    match add_new_user(&pool, &new_user) {
+        Ok(_) => {}
+        // 并发注册时唯一约束兜底
+        Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+            return HttpResponse::Conflict().json(json!({"message": "用户名或邮箱已被注册"}));
+        }
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+
+    if let Err(err) = send_verification_email(&pool, mailer.get_ref(), new_user.user_id, &payload.username, email) {
+        println!("验证邮件发送失败: {}", err);
+    }
+
+    HttpResponse::Ok().json(json!({"message": "注册成功，请查收验证邮件"}))
+}
+
+fn send_verification_email(pool: &DbPool, mailer: &dyn Mailer, user_id: Uuid, username: &str, email: &str) -> Result<(), String> {
+    let verify_token = generate_token();
+    let new_token = NewEmailVerificationToken::new(user_id, email, &hash_token(&verify_token));
+    add_email_verification_token(pool, &new_token).map_err(|err| err.to_string())?;
+
+    let verify_url = env::var("EMAIL_VERIFY_URL")
+        .unwrap_or_else(|_| String::from("http://localhost:3000/verify-email"));
+    let body = format!(
+        "{}，你好：\n\n请在 {} 小时内打开以下链接验证邮箱：\n{}?token={}",
+        username, EMAIL_VERIFICATION_TTL_HOURS, verify_url, verify_token
+    );
+
+    mailer.send(email, "验证邮箱", &body).map_err(|err| err.to_string())
+}
+
+pub async fn email_verify(
+    pool: web::Data<DbPool>,
+    payload: web::Json<VerifyEmailPayload>,
+) -> impl Responder {
+    match verify_email_with_token(&pool, &hash_token(&payload.token)) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "邮箱验证成功"})),
+        Err(diesel::result::Error::NotFound) => HttpResponse::BadRequest().json(json!({"message": "验证链接无效或已过期"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+pub async fn email_verify_resend(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+) -> impl Responder {
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if account.email_verified_at.is_some() {
+        return HttpResponse::BadRequest().json(json!({"message": "邮箱已验证"}));
+    }
+
+    match send_verification_email(&pool, mailer.get_ref(), account.user_id, &account.username, &account.email) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "验证邮件已发送"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err})),
+    }
+}
+
+// 开启 REQUIRE_EMAIL_VERIFICATION 时，邮箱未验证的用户不能创建对话
+fn unverified_email(pool: &DbPool, userid: Uuid) -> Option<HttpResponse> {
+    if !env_flag("REQUIRE_EMAIL_VERIFICATION") {
+        return None;
+    }
+    match get_user_by_id(pool, userid) {
+        Ok(account) if account.email_verified_at.is_none() => {
+            Some(HttpResponse::Forbidden().json(json!({"message": "请先验证邮箱"})))
+        }
+        Ok(_) => None,
+        Err(err) => Some(HttpResponse::InternalServerError().json(json!({"message": err.to_string()})))
+    }
+}
+
+// 标题至少 3 个字节，不超过数据库列的 100 个字符
+fn invalid_chat_title(title: &str) -> Option<HttpResponse> {
+    if title.len() < 3 {
+        return Some(HttpResponse::BadRequest().json(json!({"message": "标题过短"})));
+    }
+    if title.chars().count() > 100 {
+        return Some(HttpResponse::BadRequest().json(json!({"message": "标题过长"})));
+    }
+    None
+}
+
+pub async fn chat_new(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<NewChatPayload>, 
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    if let Some(response) = invalid_chat_title(&payload.title) {
+        return response;
+    }
+
+    if let Some(response) = unverified_email(&pool, user.user_id) {
+        return response;
+    }
+
+    // 在班级内创建的对话会使用班级的系统提示词，只有班级成员和任课教师可以这样创建
+    let classroom_id = match payload.classroom_id.as_deref() {
+        Some(classroom_id) => {
+            let classroom_uuid = match Uuid::from_str(classroom_id) {
+                Ok(data) => data,
+                Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+            };
+            match get_classroom_for_user(&pool, classroom_uuid, user.user_id) {
+                Ok(classroom) => Some(classroom.classroom_id),
+                Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "班级不存在"})),
+                Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+            }
+        }
+        None => None,
+    };
+
+    let new_chat = NewChat::new(user.user_id, &payload.title).with_classroom(classroom_id);
+
+    if let Err(err) = add_new_chat(&pool, &new_chat){
+        HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    }else{
+        HttpResponse::Ok().json(json!({"message": "创建对话成功", "chat_id": new_chat.chat_id.to_string()}))
+    }
+}
+
+// 游标无法解析或与本次排序方式不一致时返回 400
+fn parse_chat_cursor(raw: Option<&str>, sort: ChatSort) -> Result<Option<ChatCursor>, HttpResponse> {
+    match raw.map(ChatCursor::decode) {
+        None => Ok(None),
+        Some(Some(cursor)) if cursor.sort == sort => Ok(Some(cursor)),
+        Some(_) => Err(HttpResponse::BadRequest().json(json!({"message": "无效的翻页游标"}))),
+    }
+}
+
+//  /v1/chat/history?limit=&before=&after=&sort=created|activity
+pub async fn chat_history(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    query: web::Query<ChatHistoryQuery>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_READ) {
+        return response;
+    }
+
+    let query = query.into_inner();
+    if query.before.is_some() && query.after.is_some() {
+        return HttpResponse::BadRequest().json(json!({"message": "before 和 after 不能同时使用"}));
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+
+    let before = match parse_chat_cursor(query.before.as_deref(), query.sort) {
+        Ok(cursor) => cursor,
+        Err(response) => return response,
+    };
+    let after = match parse_chat_cursor(query.after.as_deref(), query.sort) {
+        Ok(cursor) => cursor,
+        Err(response) => return response,
+    };
+
+    let folder_id = match query.folder_id.as_deref().map(parse_folder_ref).transpose() {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let tag_id = match query.tag_id.as_deref().map(Uuid::from_str).transpose() {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    let filter = ChatListFilter {
+        archived: query.archived,
+        pinned: query.pinned,
+        folder_id,
+        tag_id,
+    };
+
+    let (chats, has_more) = match get_chats_page(&pool, user.user_id, query.sort, &filter, before, after, limit){
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    // 沿请求方向继续翻页：after 方向取最新的一条，否则取最旧的一条
+    let next_cursor = if !has_more {
+        None
+    } else if after.is_some() {
+        chats.first().map(|chat| ChatCursor::from_chat(chat, query.sort).encode())
+    } else {
+        chats.last().map(|chat| ChatCursor::from_chat(chat, query.sort).encode())
+    };
+
+    let chat_uuids: Vec<Uuid> = chats.iter().map(|chat| chat.chat_id).collect();
+    let chat_tags = match get_tags_for_chats(&pool, &chat_uuids) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let mut chats_fliter: Vec<Value> = vec![];
+
+    for chat in chats {
+        let tags_json: Vec<Value> = chat_tags.iter()
+            .filter(|(chat_uuid, _)| *chat_uuid == chat.chat_id)
+            .map(|(_, tag)| json!({"tag_id": tag.tag_id.to_string(), "name": tag.name}))
+            .collect();
+        let chat_json = json!({
+            "chat_id": chat.chat_id.to_string(),
+            "title": chat.title,
+            "created_at": chat.created_at.to_string(),
+            "last_message_at": chat.last_message_at.to_string(),
+            "pinned": chat.pinned,
+            "archived": chat.archived,
+            "folder_id": chat.folder_id.map(|id| id.to_string()),
+            "tags": tags_json,
+            "classroom_id": chat.classroom_id.map(|id| id.to_string()),
+            "forked_from_chat_id": chat.forked_from_chat_id.map(|id| id.to_string()),
+            "forked_from_message_id": chat.forked_from_message_id.map(|id| id.to_string())
+        });
+        chats_fliter.push(chat_json);
+    }
+
+    let response = json!({
+        "chats": chats_fliter,
+        "next_cursor": next_cursor,
+        "status": "200",
+        "message": "查询历史对话成功"
+    });
+
+    HttpResponse::Ok().json(response)
+}
+
+//  /v1/chat/search?q=&limit=&offset=
+//  摘要已做 HTML 转义，其中只有 <mark> 是标签，前端可以直接按 HTML 展示
+pub async fn chat_search(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+    query: web::Query<ChatSearchQuery>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_READ) {
+        return response;
+    }
+
+    let keywords = query.q.trim();
+    if keywords.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"message": "搜索关键词不能为空"}));
+    }
+    if keywords.chars().count() > 200 {
+        return HttpResponse::BadRequest().json(json!({"message": "搜索关键词过长"}));
+    }
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let hits = match search_chats(&pool, user.user_id, keywords, limit, offset) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let total = hits.first().map(|hit| hit.total).unwrap_or(0);
+    let next_offset = (offset + (hits.len() as i64) < total).then_some(offset + hits.len() as i64);
+
+    let results: Vec<Value> = hits.iter().map(|hit| json!({
+        "chat_id": hit.chat_id.to_string(),
+        "title": hit.title,
+        "message_id": hit.message_id.map(|id| id.to_string()),
+        "seq": hit.seq,
+        "role": hit.role,
+        "snippet": hit.snippet,
+        "rank": hit.rank,
+        "matched_at": hit.matched_at.to_string()
+    })).collect();
+
+    HttpResponse::Ok().json(json!({
+        "results": results,
+        "total": total,
+        "next_offset": next_offset,
+        "status": "200",
+        "message": "搜索成功"
+    }))
+}
+
+fn message_json(msg: &Message) -> Value {
+    json!({
+        "message_id": msg.message_id.to_string(),
+        "seq": msg.seq,
+        "parent_message_id": msg.parent_message_id.map(|id| id.to_string()),
+        "role": msg.role,
+        "content": msg.content,
+        "timestamp": msg.timestamp.map(|t| t.to_string())
+    })
+}
+
+// 附带同级分支信息，`sibling_index` 从 1 开始，供客户端显示“2 / 3”并切换分支
+fn branch_message_json(msg: &BranchMessage) -> Value {
+    let mut value = message_json(&msg.message);
+    let sibling_index = msg.sibling_ids.iter().position(|id| *id == msg.message.message_id).map(|i| i + 1);
+
+    value["sibling_ids"] = json!(msg.sibling_ids.iter().map(|id| id.to_string()).collect::<Vec<String>>());
+    value["sibling_count"] = json!(msg.sibling_ids.len());
+    value["sibling_index"] = json!(sibling_index);
+    value
+}
+
+//  /v1/chat/{chat_id}?limit=&before_seq=，返回当前分支
+pub async fn chat_content(
+    user: AuthenticatedUser,
+    chat_id: web::Path<String>,
+    pool: Data<DbPool>,
+    query: web::Query<MessagePageQuery>,
+) -> impl Responder{
+    if let Err(response) = user.require_scope(SCOPE_CHAT_READ) {
+        return response;
+    }
+
+    let chat_uuid = match Uuid::from_str(&chat_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    match get_chat_by_id(&pool, chat_uuid, user.user_id) {
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+
+    let (msgs, has_more) = match get_messages_page(&pool, chat_uuid, user.user_id, query.before_seq, limit) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    // 还有更早的消息时，以本页第一条的序号继续向前加载
+    let next_before_seq = if has_more { msgs.first().map(|msg| msg.message.seq) } else { None };
+
+    let msgs_json: Vec<Value> = msgs.iter().map(branch_message_json).collect();
+
+    let response = json!({
+        "chats": msgs_json,
+        "has_more": has_more,
+        "next_before_seq": next_before_seq,
+        "status": "200",
+        "message": "查询对话内容成功"
+    });
+
+    HttpResponse::Ok().json(json!(response))
+}
+
+//  PATCH /v1/chat/{chat_id}，修改标题、置顶、归档状态和所在文件夹
+pub async fn chat_update(
+    user: AuthenticatedUser,
+    chat_id: web::Path<String>,
+    pool: Data<DbPool>,
+    payload: web::Json<UpdateChatPayload>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let chat_uuid = match Uuid::from_str(&chat_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    let title = payload.title.as_deref().map(str::trim);
+    if let Some(response) = title.and_then(invalid_chat_title) {
+        return response;
+    }
+
+    let folder_id = match payload.folder_id.as_deref().map(parse_folder_ref).transpose() {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    if let Some(Some(folder_uuid)) = folder_id {
+        if let Err(response) = owned_folder(&pool, user.user_id, folder_uuid) {
+            return response;
+        }
+    }
+
+    let changes = ChatChangeset {
+        title: title.map(str::to_string),
+        pinned: payload.pinned,
+        archived: payload.archived,
+        folder_id,
+    };
+
+    if changes.title.is_none() && changes.pinned.is_none() && changes.archived.is_none() && changes.folder_id.is_none() {
+        return HttpResponse::BadRequest().json(json!({"message": "没有需要修改的内容"}));
+    }
+
+    match update_chat(&pool, chat_uuid, user.user_id, &changes) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "对话已更新"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  DELETE /v1/chat/{chat_id}，移入回收站，保留期内可以恢复
+pub async fn chat_delete(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    chat_id: web::Path<String>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let chat_uuid = match Uuid::from_str(&chat_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})),
+    };
+
+    let deleted_at = now();
+    match trash_chat(&pool, chat_uuid, user.user_id, deleted_at) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Ok(_) => {
+            audit::record(&pool, &req, audit::CHAT_TRASHED, Some(user.user_id), Some(("chat", &chat_uuid.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({
+                "message": "对话已移入回收站",
+                "purge_at": (deleted_at + chrono::Duration::days(chat_trash_retention_days())).to_string()
+            }))
+        }
+        Err(_) => HttpResponse::InternalServerError().json(json!({"message": "删除失败"})),
+    }
+}
+
+//  /v1/chat/trash?limit=&offset=
+pub async fn chat_trash(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+    query: web::Query<TrashQuery>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_READ) {
+        return response;
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let chats = match get_trashed_chats(&pool, user.user_id, limit, offset) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let retention = chrono::Duration::days(chat_trash_retention_days());
+    let chats_json: Vec<Value> = chats.iter().map(|chat| json!({
+        "chat_id": chat.chat_id.to_string(),
+        "title": chat.title,
+        "created_at": chat.created_at.to_string(),
+        "last_message_at": chat.last_message_at.to_string(),
+        "deleted_at": chat.deleted_at.map(|t| t.to_string()),
+        "purge_at": chat.deleted_at.map(|t| (t + retention).to_string()),
+        "classroom_id": chat.classroom_id.map(|id| id.to_string())
+    })).collect();
+
+    HttpResponse::Ok().json(json!({
+        "chats": chats_json,
+        "status": "200",
+        "message": "查询回收站成功"
+    }))
+}
+
+//  POST /v1/chat/trash/{chat_id}/restore
+pub async fn chat_restore(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    chat_id: web::Path<String>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let chat_uuid = match Uuid::from_str(&chat_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})),
+    };
+
+    match restore_chat(&pool, chat_uuid, user.user_id) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "回收站中没有该对话"})),
+        Ok(_) => {
+            audit::record(&pool, &req, audit::CHAT_RESTORED, Some(user.user_id), Some(("chat", &chat_uuid.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "对话已恢复"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  DELETE /v1/chat/trash/{chat_id}，彻底删除，只能删除回收站中的对话
+pub async fn chat_purge(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    chat_id: web::Path<String>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let chat_uuid = match Uuid::from_str(&chat_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})),
+    };
+
+    match delete_chat(&pool, chat_uuid, user.user_id) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::CHAT_DELETED, Some(user.user_id), Some(("chat", &chat_uuid.to_string())), json!({}));
+            HttpResponse::Ok().json(json!({"message": "对话已彻底删除"}))
+        }
+        Err(diesel::result::Error::NotFound) => HttpResponse::NotFound().json(json!({"message": "回收站中没有该对话"})),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"message": "删除失败"})),
+    }
+}
+
+// 文件夹引用：空字符串表示顶层
+fn parse_folder_ref(raw: &str) -> Result<Option<Uuid>, HttpResponse> {
+    if raw.is_empty() {
+        return Ok(None);
+    }
+    Uuid::from_str(raw)
+        .map(Some)
+        .map_err(|_| HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})))
+}
+
+// 文件夹不存在或不属于该用户时返回 404
+fn owned_folder(pool: &DbPool, userid: Uuid, folder_uuid: Uuid) -> Result<Folder, HttpResponse> {
+    match get_folder(pool, folder_uuid, userid) {
+        Ok(folder) => Ok(folder),
+        Err(diesel::result::Error::NotFound) => Err(HttpResponse::NotFound().json(json!({"message": "文件夹不存在"}))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))),
+    }
+}
+
+// 批量操作的对话 ID，去重后最多 200 个
+fn parse_chat_ids(raw: &[String]) -> Result<Vec<Uuid>, HttpResponse> {
+    let mut chat_uuids: Vec<Uuid> = Vec::with_capacity(raw.len());
+    for id in raw {
+        let chat_uuid = Uuid::from_str(id)
+            .map_err(|_| HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})))?;
+        if !chat_uuids.contains(&chat_uuid) {
+            chat_uuids.push(chat_uuid);
+        }
+    }
+    if chat_uuids.is_empty() || chat_uuids.len() > 200 {
+        return Err(HttpResponse::BadRequest().json(json!({"message": "对话数量需在 1 到 200 之间"})));
+    }
+    Ok(chat_uuids)
+}
+
+fn normalize_folder_name(name: &str) -> Result<String, HttpResponse> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(HttpResponse::BadRequest().json(json!({"message": "文件夹名称长度不合法"})));
+    }
+    Ok(name.to_string())
+}
+
+fn normalize_tag_name(name: &str) -> Result<String, HttpResponse> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 50 {
+        return Err(HttpResponse::BadRequest().json(json!({"message": "标签名称长度不合法"})));
+    }
+    Ok(name.to_string())
+}
+
+fn folder_json(folder: &Folder) -> Value {
+    json!({
+        "folder_id": folder.folder_id.to_string(),
+        "parent_id": folder.parent_id.map(|id| id.to_string()),
+        "name": folder.name,
+        "created_at": folder.created_at.to_string()
+    })
+}
+
+//  POST /v1/chat/bulk/move，批量移动到文件夹，任何一个对话不可用时整体不生效
+pub async fn chat_bulk_move(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+    payload: web::Json<BulkMovePayload>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let chat_uuids = match parse_chat_ids(&payload.chat_ids) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let folder_id = match payload.folder_id.as_deref().map(parse_folder_ref).transpose() {
+        Ok(data) => data.flatten(),
+        Err(response) => return response,
+    };
+    if let Some(folder_uuid) = folder_id {
+        if let Err(response) = owned_folder(&pool, user.user_id, folder_uuid) {
+            return response;
+        }
+    }
+
+    match move_chats(&pool, user.user_id, &chat_uuids, folder_id) {
+        Ok(count) => HttpResponse::Ok().json(json!({"message": "对话已移动", "count": count})),
+        Err(diesel::result::Error::NotFound) => HttpResponse::NotFound().json(json!({"message": "部分对话不存在"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  POST /v1/chat/bulk/tag，批量添加和移除标签，任何一个对话不可用时整体不生效
+pub async fn chat_bulk_tag(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+    payload: web::Json<BulkTagPayload>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let chat_uuids = match parse_chat_ids(&payload.chat_ids) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let add = match payload.add.iter().map(|name| normalize_tag_name(name)).collect::<Result<Vec<String>, HttpResponse>>() {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let remove = match payload.remove.iter().map(|name| normalize_tag_name(name)).collect::<Result<Vec<String>, HttpResponse>>() {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    if add.is_empty() && remove.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"message": "没有需要修改的标签"}));
+    }
+    if add.len() > 20 || remove.len() > 20 {
+        return HttpResponse::BadRequest().json(json!({"message": "一次最多修改 20 个标签"}));
+    }
+
+    match tag_chats(&pool, user.user_id, &chat_uuids, &add, &remove) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "标签已更新"})),
+        Err(diesel::result::Error::NotFound) => HttpResponse::NotFound().json(json!({"message": "部分对话不存在"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/folder/new
+pub async fn folder_new(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+    payload: web::Json<NewFolderPayload>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let name = match normalize_folder_name(&payload.name) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let parent_id = match payload.parent_id.as_deref().map(parse_folder_ref).transpose() {
+        Ok(data) => data.flatten(),
+        Err(response) => return response,
+    };
+    if let Some(parent_uuid) = parent_id {
+        if let Err(response) = owned_folder(&pool, user.user_id, parent_uuid) {
+            return response;
+        }
+    }
+
+    let folder = Folder::new(user.user_id, parent_id, &name);
+    match add_folder(&pool, &folder) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "文件夹创建成功", "folder": folder_json(&folder)})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/folder/list，返回全部文件夹，客户端按 parent_id 组装成树
+pub async fn folder_list(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_READ) {
+        return response;
+    }
+
+    match get_folders_by_user_id(&pool, user.user_id) {
+        Ok(folders) => HttpResponse::Ok().json(json!({
+            "folders": folders.iter().map(folder_json).collect::<Vec<Value>>(),
+            "status": "200",
+            "message": "查询文件夹成功"
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  PATCH /v1/folder/{folder_id}，重命名或移动到其他文件夹下
+pub async fn folder_update(
+    user: AuthenticatedUser,
+    folder_id: web::Path<String>,
+    pool: Data<DbPool>,
+    payload: web::Json<UpdateFolderPayload>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let folder_uuid = match Uuid::from_str(&folder_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+    let folder = match owned_folder(&pool, user.user_id, folder_uuid) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let name = match payload.name.as_deref().map(normalize_folder_name).transpose() {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let parent_id = match payload.parent_id.as_deref().map(parse_folder_ref).transpose() {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    // 新的上级不能是自己或自己的子文件夹
+    if let Some(Some(parent_uuid)) = parent_id {
+        if let Err(response) = owned_folder(&pool, user.user_id, parent_uuid) {
+            return response;
+        }
+        match get_folder_path(&pool, parent_uuid) {
+            Ok(path) if path.contains(&folder.folder_id) => {
+                return HttpResponse::BadRequest().json(json!({"message": "不能移动到自身或子文件夹下"}));
+            }
+            Ok(_) => {}
+            Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+        }
+    }
+
+    let changes = FolderChangeset { name, parent_id };
+    if changes.name.is_none() && changes.parent_id.is_none() {
+        return HttpResponse::BadRequest().json(json!({"message": "没有需要修改的内容"}));
+    }
+
+    match update_folder(&pool, folder.folder_id, &changes) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "文件夹已更新"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  DELETE /v1/folder/{folder_id}，子文件夹一并删除，其中的对话回到顶层
+pub async fn folder_delete(
+    user: AuthenticatedUser,
+    folder_id: web::Path<String>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let folder_uuid = match Uuid::from_str(&folder_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    match delete_folder(&pool, folder_uuid, user.user_id) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "文件夹不存在"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "文件夹已删除"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/tag/new
+pub async fn tag_new(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+    payload: web::Json<TagPayload>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let name = match normalize_tag_name(&payload.name) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let tag = Tag::new(user.user_id, &name);
+    match add_tag(&pool, &tag) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "标签创建成功", "tag_id": tag.tag_id.to_string()})),
+        Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+            HttpResponse::Conflict().json(json!({"message": "标签已存在"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/tag/list，附带每个标签下的对话数
+pub async fn tag_list(
+    user: AuthenticatedUser,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_READ) {
+        return response;
+    }
+
+    match get_tags_by_user_id(&pool, user.user_id) {
+        Ok(tags) => HttpResponse::Ok().json(json!({
+            "tags": tags.iter().map(|(tag, chat_count)| json!({
+                "tag_id": tag.tag_id.to_string(),
+                "name": tag.name,
+                "chat_count": chat_count,
+                "created_at": tag.created_at.to_string()
+            })).collect::<Vec<Value>>(),
+            "status": "200",
+            "message": "查询标签成功"
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  PATCH /v1/tag/{tag_id}，重命名
+pub async fn tag_update(
+    user: AuthenticatedUser,
+    tag_id: web::Path<String>,
+    pool: Data<DbPool>,
+    payload: web::Json<TagPayload>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let tag_uuid = match Uuid::from_str(&tag_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    let name = match normalize_tag_name(&payload.name) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match rename_tag(&pool, tag_uuid, user.user_id, &name) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "标签不存在"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "标签已更新"})),
+        Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+            HttpResponse::Conflict().json(json!({"message": "标签已存在"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  DELETE /v1/tag/{tag_id}，同时从所有对话上移除
+pub async fn tag_delete(
+    user: AuthenticatedUser,
+    tag_id: web::Path<String>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let tag_uuid = match Uuid::from_str(&tag_id.into_inner()) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    match delete_tag(&pool, tag_uuid, user.user_id) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "标签不存在"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "标签已删除"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+fn parse_chat_message_path(path: (String, String)) -> Result<(Uuid, Uuid), HttpResponse> {
+    match (Uuid::from_str(&path.0), Uuid::from_str(&path.1)) {
+        (Ok(chat_uuid), Ok(message_uuid)) => Ok((chat_uuid, message_uuid)),
+        _ => Err(HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))),
+    }
+}
+
+// 退出班级后不再使用该班级的系统提示词
+fn chat_system_prompt(pool: &DbPool, chat: &Chat, userid: Uuid) -> Option<String> {
+    let classroom_uuid = chat.classroom_id?;
+    get_classroom_for_user(pool, classroom_uuid, userid).ok()?.system_prompt
+}
+
+// `message_uuid` 之前的分支消息，作为上下文一起转发
+fn branch_history(pool: &DbPool, message_uuid: Uuid) -> Result<Vec<HistoryMessage>, diesel::result::Error> {
+    let mut msgs = get_branch_messages(pool, message_uuid, STREAM_HISTORY_LIMIT + 1)?;
+    msgs.pop();
+
+    Ok(msgs.into_iter().map(|msg| HistoryMessage { role: msg.role, content: msg.content }).collect())
+}
+
+// 转发给模型服务并流式返回，结束后把回复接在 `user_message_id` 后面保存。
+// 响应头 X-Message-Id 为本次提问的消息 ID
+async fn stream_assistant_reply(pool: Data<DbPool>, payload: &ChatPayload, chat_id: Uuid, user_message_id: Uuid) -> HttpResponse {
+    let client = Client::new();
+    let url = "http://localhost:8000/stream";
+
+    let res = match client.post(url).json(payload).send().await {
+        Ok(res) => res,
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to connect to backend"),
+    };
+
+    let (tx, mut rx) = mpsc::channel::<Bytes>(10);
+    let mut collected_response = Vec::new();
+
+    let mut stream = res.bytes_stream();
+
+    tokio::spawn(async move {
+        loop {
+            let chunk = match stream.try_next().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(err) => {
+                    println!("模型服务响应中断: {}", err);
+                    return;
+                }
+            };
+            collected_response.extend_from_slice(&chunk);
+
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+
+        let json_str = String::from_utf8_lossy(&collected_response).into_owned();
+        let parsed: Value = match serde_json::from_str(&json_str) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                println!("模型服务响应解析失败: {}", err);
+                return;
+            }
+        };
+        let chunks = match parsed["response"].as_array() {
+            Some(chunks) => chunks,
+            None => {
+                println!("模型服务响应缺少 response 字段");
+                return;
+            }
+        };
+
+        let result: String = chunks
+            .iter()
+            .map(|item| item["chunk"].as_str().unwrap_or(""))
+            .collect();
+
+        let ai_msg: NewMessage = NewMessage::new(chat_id, &String::from("assistant"), &result);
+        if let Err(err) = add_branch_message(&pool, &ai_msg, Some(user_message_id)) {
+            println!("回复保存失败: {}", err);
+        }
+    });
+
+    let response_stream = async_stream::stream! {
+        while let Some(chunk) = rx.recv().await {
+            yield Ok::<Bytes, actix_web::Error>(chunk);
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(("X-Message-Id", user_message_id.to_string()))
+        .streaming(response_stream)
+}
+
+pub async fn proxy_stream(
+    user: AuthenticatedUser,
+    req_body: web::Json<ChatPayload>,
+    pool: Data<DbPool>
+) -> impl Responder {
+    let mut req_body = req_body.into_inner();
+
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let prompt = req_body.prompt.clone();
+    let chat_id = match Uuid::from_str(&req_body.chat_id) {
+        Ok(data) => data,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"}))
+    };
+
+    let chat = match get_chat_by_id(&pool, chat_id, user.user_id) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    req_body.system_prompt = chat_system_prompt(&pool, &chat, user.user_id);
+
+    let user_msg: NewMessage = NewMessage::new(chat_id, &String::from("user"), &prompt);
+
+    if let Err(err) = add_new_message(&pool, &user_msg) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    req_body.history = match branch_history(&pool, user_msg.message_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    stream_assistant_reply(pool, &req_body, chat_id, user_msg.message_id).await
+}
+
+//  POST /v1/chat/{chat_id}/messages/{message_id}/edit
+//  以新内容创建该提问的同级分支，并从这里重新生成回答
+pub async fn chat_message_edit(
+    user: AuthenticatedUser,
+    path: web::Path<(String, String)>,
+    payload: web::Json<EditMessagePayload>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let (chat_uuid, message_uuid) = match parse_chat_message_path(path.into_inner()) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let chat = match get_chat_by_id(&pool, chat_uuid, user.user_id) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let original = match get_message(&pool, chat_uuid, message_uuid) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "消息不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if original.role != "user" {
+        return HttpResponse::BadRequest().json(json!({"message": "只能编辑用户发送的消息"}));
+    }
+
+    let prompt = payload.into_inner().prompt;
+
+    if prompt.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({"message": "消息内容不能为空"}));
+    }
+
+    let user_msg: NewMessage = NewMessage::new(chat_uuid, &String::from("user"), &prompt);
+
+    if let Err(err) = add_branch_message(&pool, &user_msg, original.parent_message_id) {
+        return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}));
+    }
+
+    let history = match branch_history(&pool, user_msg.message_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let req_body = ChatPayload {
+        prompt,
+        chat_id: chat_uuid.to_string(),
+        system_prompt: chat_system_prompt(&pool, &chat, user.user_id),
+        history,
+    };
+
+    stream_assistant_reply(pool, &req_body, chat_uuid, user_msg.message_id).await
+}
+
+//  POST /v1/chat/{chat_id}/messages/{message_id}/regenerate
+//  为同一次提问重新生成回答，`message_id` 可以是提问或其任一回答。之前的回答作为同级分支保留
+pub async fn chat_message_regenerate(
+    user: AuthenticatedUser,
+    path: web::Path<(String, String)>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let (chat_uuid, message_uuid) = match parse_chat_message_path(path.into_inner()) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let chat = match get_chat_by_id(&pool, chat_uuid, user.user_id) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let message = match get_message(&pool, chat_uuid, message_uuid) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "消息不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let turn = match (message.role.as_str(), message.parent_message_id) {
+        ("user", _) => message,
+        ("assistant", Some(parent_uuid)) => match get_message(&pool, chat_uuid, parent_uuid) {
+            Ok(data) => data,
+            Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+        },
+        _ => return HttpResponse::BadRequest().json(json!({"message": "该消息没有对应的提问"})),
+    };
+
+    if turn.role != "user" {
+        return HttpResponse::BadRequest().json(json!({"message": "该消息没有对应的提问"}));
+    }
+
+    let history = match branch_history(&pool, turn.message_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let req_body = ChatPayload {
+        prompt: turn.content,
+        chat_id: chat_uuid.to_string(),
+        system_prompt: chat_system_prompt(&pool, &chat, user.user_id),
+        history,
+    };
+
+    stream_assistant_reply(pool, &req_body, chat_uuid, turn.message_id).await
+}
+
+//  GET /v1/chat/{chat_id}/messages/{message_id}/alternatives
+//  该消息的全部同级版本（编辑后的提问或重新生成的回答），用 activate 选定其中一个
+pub async fn chat_message_alternatives(
+    user: AuthenticatedUser,
+    path: web::Path<(String, String)>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_READ) {
+        return response;
+    }
+
+    let (chat_uuid, message_uuid) = match parse_chat_message_path(path.into_inner()) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match get_chat_by_id(&pool, chat_uuid, user.user_id) {
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let message = match get_message(&pool, chat_uuid, message_uuid) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "消息不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    match get_sibling_messages(&pool, &message) {
+        Ok(siblings) => HttpResponse::Ok().json(json!({
+            "alternatives": siblings.iter().map(message_json).collect::<Vec<Value>>(),
+            "status": "200",
+            "message": "查询成功"
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  POST /v1/chat/{chat_id}/messages/{message_id}/fork
+//  把来源对话从开头到该消息的分支复制到调用者名下的新对话。来源可以是自己的对话，
+//  也可以是任教班级中学生共享的对话
+pub async fn chat_message_fork(
+    user: AuthenticatedUser,
+    path: web::Path<(String, String)>,
+    payload: web::Json<ForkChatPayload>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let (chat_uuid, message_uuid) = match parse_chat_message_path(path.into_inner()) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let source = match get_shared_chat(&pool, chat_uuid, user.user_id) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    match get_message(&pool, chat_uuid, message_uuid) {
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "消息不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let title = payload.into_inner().title.unwrap_or_else(|| source.title.clone());
+
+    if let Some(response) = invalid_chat_title(&title) {
+        return response;
+    }
+
+    if let Some(response) = unverified_email(&pool, user.user_id) {
+        return response;
+    }
+
+    // 分叉自己的对话时沿用班级，分叉学生的对话时不归属任何班级
+    let classroom_id = if source.user_id == user.user_id { source.classroom_id } else { None };
+
+    let new_chat = NewChat::new(user.user_id, &title)
+        .with_classroom(classroom_id)
+        .forked_from(chat_uuid, message_uuid);
+
+    match fork_chat(&pool, &new_chat, message_uuid) {
+        Ok(message_count) => HttpResponse::Ok().json(json!({
+            "chat_id": new_chat.chat_id.to_string(),
+            "forked_from_chat_id": chat_uuid.to_string(),
+            "forked_from_message_id": message_uuid.to_string(),
+            "message_count": message_count,
+            "message": "分叉对话成功"
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  POST /v1/chat/{chat_id}/messages/{message_id}/activate
+//  切换到该消息所在的分支，末尾为其下最新的一条消息
+pub async fn chat_message_activate(
+    user: AuthenticatedUser,
+    path: web::Path<(String, String)>,
+    pool: Data<DbPool>,
+) -> impl Responder {
+    if let Err(response) = user.require_scope(SCOPE_CHAT_WRITE) {
+        return response;
+    }
+
+    let (chat_uuid, message_uuid) = match parse_chat_message_path(path.into_inner()) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match get_chat_by_id(&pool, chat_uuid, user.user_id) {
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "对话不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    match get_message(&pool, chat_uuid, message_uuid) {
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "消息不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    match activate_branch(&pool, chat_uuid, message_uuid) {
+        Ok(leaf) => HttpResponse::Ok().json(json!({
+            "active_message_id": leaf.to_string(),
+            "message": "已切换分支"
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+pub async fn ocr_handle(
+    user: AuthenticatedUser,
+    payload: web::Json<OCRPalyload>,
+    pool: Data<DbPool>,
+) -> impl Responder{
+    if let Err(response) = user.require_scope(SCOPE_OCR) {
+        return response;
+    }
 
     let result: String = match img2latex(&payload.imgb64).await{
         Ok(data) => data,
-        Err(err) => return HttpResponse::BadRequest().json(json!({"message": err.to_string()})),
+        Err(err) => return HttpResponse::BadRequest().json(json!({"message": err.to_string()})),
+    };
+
+    if let Err(err) = add_ocr_record(&pool, &OcrRecord::new(user.user_id, &result)) {
+        println!("OCR 记录保存失败: {}", err);
+    }
+
+    HttpResponse::BadRequest().json(
+        json!({"message": "扫描成功", "content": result}))
+}
+
+
+fn classroom_json(classroom: &Classroom, is_teacher: bool) -> Value {
+    let mut classroom_json = json!({
+        "classroom_id": classroom.classroom_id.to_string(),
+        "name": classroom.name,
+        "teacher_id": classroom.teacher_id.to_string(),
+        "created_at": classroom.created_at.map(|t| t.to_string())
+    });
+    // 邀请码和系统提示词只对任课教师可见
+    if is_teacher {
+        classroom_json["invite_code"] = json!(classroom.invite_code);
+        classroom_json["system_prompt"] = json!(classroom.system_prompt);
+    }
+    classroom_json
+}
+
+fn parse_classroom_id(classroom_id: &str) -> Result<Uuid, HttpResponse> {
+    Uuid::parse_str(classroom_id).map_err(|_| HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})))
+}
+
+/// 只有任课教师可以管理班级，其他用户一律视为班级不存在
+fn taught_classroom(pool: &DbPool, user: &SessionUser, classroom_id: &str) -> Result<Classroom, HttpResponse> {
+    let classroom_uuid = parse_classroom_id(classroom_id)?;
+    match get_classroom_by_id(pool, classroom_uuid) {
+        Ok(classroom) if classroom.teacher_id == user.user_id => Ok(classroom),
+        Ok(_) | Err(diesel::result::Error::NotFound) => Err(HttpResponse::NotFound().json(json!({"message": "班级不存在"}))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))),
+    }
+}
+
+fn normalize_system_prompt(system_prompt: Option<&str>) -> Option<String> {
+    system_prompt.map(str::trim).filter(|p| !p.is_empty()).map(str::to_string)
+}
+
+//  /v1/classroom/new
+pub async fn classroom_new(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<NewClassroomPayload>,
+) -> impl Responder {
+    if !user.role.has_permission(Permission::ManageClassrooms) {
+        return HttpResponse::Forbidden().json(json!({"message": "只有教师可以创建班级"}));
+    }
+
+    let name = payload.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return HttpResponse::BadRequest().json(json!({"message": "班级名称长度不合法"}));
+    }
+
+    let classroom = Classroom::new(user.user_id, name, normalize_system_prompt(payload.system_prompt.as_deref()));
+
+    match add_classroom(&pool, &classroom) {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "创建班级成功",
+            "classroom": classroom_json(&classroom, true)
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/classroom/list，包括任教的班级和已加入的班级
+pub async fn classroom_list(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let teaching = match get_classrooms_by_teacher_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let joined = match get_joined_classrooms(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let teaching_json: Vec<Value> = teaching.iter().map(|classroom| classroom_json(classroom, true)).collect();
+    let joined_json: Vec<Value> = joined.iter().map(|(classroom, member)| {
+        let mut classroom_json = classroom_json(classroom, false);
+        classroom_json["share_chats"] = json!(member.share_chats);
+        classroom_json["joined_at"] = json!(member.joined_at.map(|t| t.to_string()));
+        classroom_json
+    }).collect();
+
+    HttpResponse::Ok().json(json!({
+        "teaching": teaching_json,
+        "joined": joined_json,
+        "status": "200",
+        "message": "查询班级成功"
+    }))
+}
+
+//  /v1/classroom/join
+pub async fn classroom_join(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<JoinClassroomPayload>,
+) -> impl Responder {
+    let invite_code = payload.invite_code.trim().to_uppercase();
+
+    let classroom = match get_classroom_by_invite_code(&pool, &invite_code) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "邀请码无效"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if classroom.teacher_id == user.user_id {
+        return HttpResponse::BadRequest().json(json!({"message": "不能加入自己任教的班级"}));
+    }
+
+    let member = ClassroomMember::new(classroom.classroom_id, user.user_id, payload.share_chats);
+
+    match add_classroom_member(&pool, &member) {
+        Ok(0) => HttpResponse::Ok().json(json!({"message": "已在该班级中", "classroom": classroom_json(&classroom, false)})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "加入班级成功", "classroom": classroom_json(&classroom, false)})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/classroom/{classroom_id}
+pub async fn classroom_detail(
+    user: SessionUser,
+    classroom_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let classroom_uuid = match parse_classroom_id(&classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match get_classroom_for_user(&pool, classroom_uuid, user.user_id) {
+        Ok(classroom) => {
+            let is_teacher = classroom.teacher_id == user.user_id;
+            HttpResponse::Ok().json(classroom_json(&classroom, is_teacher))
+        }
+        Err(diesel::result::Error::NotFound) => HttpResponse::NotFound().json(json!({"message": "班级不存在"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  PATCH /v1/classroom/{classroom_id}
+pub async fn classroom_update(
+    user: SessionUser,
+    classroom_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+    payload: web::Json<UpdateClassroomPayload>,
+) -> impl Responder {
+    let classroom = match taught_classroom(&pool, &user, &classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let name = payload.name.as_deref().map(str::trim);
+    if name.is_some_and(|name| name.is_empty() || name.chars().count() > 100) {
+        return HttpResponse::BadRequest().json(json!({"message": "班级名称长度不合法"}));
+    }
+
+    let changes = ClassroomChangeset {
+        name: name.map(str::to_string),
+        system_prompt: payload.system_prompt.as_deref().map(|p| normalize_system_prompt(Some(p))),
+    };
+
+    if changes.name.is_none() && changes.system_prompt.is_none() {
+        return HttpResponse::BadRequest().json(json!({"message": "没有需要修改的内容"}));
+    }
+
+    match update_classroom(&pool, classroom.classroom_id, &changes) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "班级已更新"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  DELETE /v1/classroom/{classroom_id}，班级内的对话保留，只解除与班级的关联
+pub async fn classroom_delete(
+    user: SessionUser,
+    classroom_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let classroom = match taught_classroom(&pool, &user, &classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match delete_classroom(&pool, classroom.classroom_id) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "班级已删除"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/classroom/{classroom_id}/invite-code，重新生成邀请码，旧邀请码立即失效
+pub async fn classroom_invite_reset(
+    user: SessionUser,
+    classroom_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let classroom = match taught_classroom(&pool, &user, &classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let invite_code = generate_invite_code();
+
+    match update_classroom_invite_code(&pool, classroom.classroom_id, &invite_code) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "邀请码已更新", "invite_code": invite_code})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/classroom/{classroom_id}/leave
+pub async fn classroom_leave(
+    user: SessionUser,
+    classroom_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let classroom_uuid = match parse_classroom_id(&classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match remove_classroom_member(&pool, classroom_uuid, user.user_id) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "未加入该班级"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "已退出班级"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/classroom/{classroom_id}/consent，学生授权或撤回教师查看自己的对话
+pub async fn classroom_consent(
+    user: SessionUser,
+    classroom_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+    payload: web::Json<ClassroomConsentPayload>,
+) -> impl Responder {
+    let classroom_uuid = match parse_classroom_id(&classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match set_classroom_share_chats(&pool, classroom_uuid, user.user_id, payload.share_chats) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "未加入该班级"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "授权设置已更新", "share_chats": payload.share_chats})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/classroom/{classroom_id}/members，未授权的学生只显示加入信息
+pub async fn classroom_members(
+    user: SessionUser,
+    classroom_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let classroom = match taught_classroom(&pool, &user, &classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let members = match get_classroom_members(&pool, classroom.classroom_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let activity = match get_classroom_activity(&pool, classroom.classroom_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let members_json: Vec<Value> = members.iter().map(|(member, username)| {
+        let mut member_json = json!({
+            "user_id": member.user_id.to_string(),
+            "username": username,
+            "share_chats": member.share_chats,
+            "joined_at": member.joined_at.map(|t| t.to_string())
+        });
+        if member.share_chats {
+            let (chat_count, message_count, last_active_at) = activity.iter()
+                .find(|item| item.user_id == member.user_id)
+                .map(|item| (item.chat_count, item.message_count, item.last_active_at))
+                .unwrap_or((0, 0, None));
+            member_json["chat_count"] = json!(chat_count);
+            member_json["message_count"] = json!(message_count);
+            member_json["last_active_at"] = json!(last_active_at.map(|t| t.to_string()));
+        }
+        member_json
+    }).collect();
+
+    HttpResponse::Ok().json(json!({
+        "members": members_json,
+        "status": "200",
+        "message": "查询班级成员成功"
+    }))
+}
+
+//  DELETE /v1/classroom/{classroom_id}/members/{user_id}
+pub async fn classroom_member_remove(
+    user: SessionUser,
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let (classroom_id, member_id) = path.into_inner();
+
+    let classroom = match taught_classroom(&pool, &user, &classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let member_uuid = match parse_user_id(&member_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match remove_classroom_member(&pool, classroom.classroom_id, member_uuid) {
+        Ok(0) => HttpResponse::NotFound().json(json!({"message": "该用户不在班级中"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "已移出班级"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/classroom/{classroom_id}/members/{user_id}/chats，只返回标题和活跃情况，不包含消息内容
+pub async fn classroom_member_chats(
+    user: SessionUser,
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let (classroom_id, member_id) = path.into_inner();
+
+    let classroom = match taught_classroom(&pool, &user, &classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let member_uuid = match parse_user_id(&member_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match get_classroom_member(&pool, classroom.classroom_id, member_uuid) {
+        Ok(member) if member.share_chats => {}
+        Ok(_) => return HttpResponse::Forbidden().json(json!({"message": "该学生未授权查看对话"})),
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "该用户不在班级中"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    }
+
+    let chats = match get_classroom_member_chats(&pool, classroom.classroom_id, member_uuid) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let chats_json: Vec<Value> = chats.iter().map(|(chat, message_count, last_active_at)| json!({
+        "chat_id": chat.chat_id.to_string(),
+        "title": chat.title,
+        "created_at": chat.created_at.to_string(),
+        "message_count": message_count,
+        "last_active_at": last_active_at.map(|t| t.to_string())
+    })).collect();
+
+    HttpResponse::Ok().json(json!({
+        "chats": chats_json,
+        "status": "200",
+        "message": "查询学生对话成功"
+    }))
+}
+
+
+fn assignment_json(assignment: &Assignment) -> Value {
+    json!({
+        "assignment_id": assignment.assignment_id.to_string(),
+        "classroom_id": assignment.classroom_id.to_string(),
+        "title": assignment.title,
+        "problems": assignment.problems,
+        "problem_latex": assignment.problem_latex,
+        "due_at": assignment.due_at.map(|t| t.to_string()),
+        "created_at": assignment.created_at.map(|t| t.to_string())
+    })
+}
+
+fn submission_json(assignment: &Assignment, submission: &AssignmentSubmission) -> Value {
+    // 截止时间之后提交的作业标记为迟交
+    let late = match (assignment.due_at, submission.submitted_at) {
+        (Some(due_at), Some(submitted_at)) => submitted_at > due_at,
+        _ => false,
+    };
+    json!({
+        "user_id": submission.user_id.to_string(),
+        "chat_id": submission.chat_id.to_string(),
+        "status": submission.status,
+        "started_at": submission.started_at.map(|t| t.to_string()),
+        "submitted_at": submission.submitted_at.map(|t| t.to_string()),
+        "reviewed_at": submission.reviewed_at.map(|t| t.to_string()),
+        "feedback": submission.feedback,
+        "late": late
+    })
+}
+
+/// 只有任课教师和班级成员可以访问作业，其他用户一律视为作业不存在
+fn visible_assignment(pool: &DbPool, userid: Uuid, assignment_id: &str) -> Result<(Assignment, Classroom), HttpResponse> {
+    let not_found = || HttpResponse::NotFound().json(json!({"message": "作业不存在"}));
+
+    let assignment_uuid = Uuid::parse_str(assignment_id)
+        .map_err(|_| HttpResponse::BadRequest().json(json!({"message": "uuid 不合法"})))?;
+
+    let assignment = match get_assignment_by_id(pool, assignment_uuid) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return Err(not_found()),
+        Err(err) => return Err(HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))),
     };
 
-    HttpResponse::BadRequest().json(
-        json!({"message": "扫描成功", "content": result}))
-    
+    match get_classroom_for_user(pool, assignment.classroom_id, userid) {
+        Ok(classroom) => Ok((assignment, classroom)),
+        Err(diesel::result::Error::NotFound) => Err(not_found()),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))),
+    }
+}
+
+/// 只有任课教师可以管理作业和查看提交情况
+fn taught_assignment(pool: &DbPool, userid: Uuid, assignment_id: &str) -> Result<Assignment, HttpResponse> {
+    match visible_assignment(pool, userid, assignment_id)? {
+        (assignment, classroom) if classroom.teacher_id == userid => Ok(assignment),
+        _ => Err(HttpResponse::Forbidden().json(json!({"message": "只有任课教师可以进行此操作"}))),
+    }
+}
+
+//  /v1/assignment/new
+pub async fn assignment_new(
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<NewAssignmentPayload>,
+) -> impl Responder {
+    let classroom = match taught_classroom(&pool, &user, &payload.classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let title = payload.title.trim();
+    if title.is_empty() || title.chars().count() > 100 {
+        return HttpResponse::BadRequest().json(json!({"message": "作业标题长度不合法"}));
+    }
+
+    let problems = payload.problems.trim();
+    if problems.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"message": "题目不能为空"}));
+    }
+
+    let problem_latex = match &payload.imgb64 {
+        Some(imgb64) => match img2latex(imgb64).await {
+            Ok(data) => Some(data),
+            Err(err) => return HttpResponse::BadRequest().json(json!({"message": format!("题目图片识别失败: {}", err)})),
+        },
+        None => None,
+    };
+
+    let assignment = Assignment::new(classroom.classroom_id, title, problems, problem_latex, payload.due_at);
+
+    match add_assignment(&pool, &assignment) {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "发布作业成功",
+            "assignment": assignment_json(&assignment)
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/assignment/list?classroom_id=，学生同时返回自己的完成状态
+pub async fn assignment_list(
+    user: SessionUser,
+    query: web::Query<AssignmentListQuery>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let classroom_uuid = match parse_classroom_id(&query.classroom_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let classroom = match get_classroom_for_user(&pool, classroom_uuid, user.user_id) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "班级不存在"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let assignments = match get_assignments_by_classroom_id(&pool, classroom.classroom_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let submissions = if classroom.teacher_id == user.user_id {
+        vec![]
+    } else {
+        let assignment_ids: Vec<Uuid> = assignments.iter().map(|a| a.assignment_id).collect();
+        match get_submissions_by_user_id(&pool, &assignment_ids, user.user_id) {
+            Ok(data) => data,
+            Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+        }
+    };
+
+    let assignments_json: Vec<Value> = assignments.iter().map(|assignment| {
+        let mut assignment_json = assignment_json(assignment);
+        if classroom.teacher_id != user.user_id {
+            assignment_json["submission"] = submissions.iter()
+                .find(|s| s.assignment_id == assignment.assignment_id)
+                .map(|s| submission_json(assignment, s))
+                .unwrap_or(Value::Null);
+        }
+        assignment_json
+    }).collect();
+
+    HttpResponse::Ok().json(json!({
+        "assignments": assignments_json,
+        "status": "200",
+        "message": "查询作业成功"
+    }))
+}
+
+//  /v1/assignment/{assignment_id}
+pub async fn assignment_detail(
+    user: SessionUser,
+    assignment_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let (assignment, classroom) = match visible_assignment(&pool, user.user_id, &assignment_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let mut assignment_json = assignment_json(&assignment);
+
+    if classroom.teacher_id != user.user_id {
+        assignment_json["submission"] = match get_submission(&pool, assignment.assignment_id, user.user_id) {
+            Ok(submission) => submission_json(&assignment, &submission),
+            Err(diesel::result::Error::NotFound) => Value::Null,
+            Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+        };
+    }
+
+    HttpResponse::Ok().json(assignment_json)
+}
+
+//  DELETE /v1/assignment/{assignment_id}，提交记录随作业删除，学生的作业对话仍保留在各自的历史中
+pub async fn assignment_delete(
+    user: SessionUser,
+    assignment_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let assignment = match taught_assignment(&pool, user.user_id, &assignment_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match delete_assignment(&pool, assignment.assignment_id) {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "作业已删除"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/assignment/{assignment_id}/start，创建以题目开头的作业对话；已开始时直接返回原对话
+pub async fn assignment_start(
+    user: SessionUser,
+    assignment_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let (assignment, classroom) = match visible_assignment(&pool, user.user_id, &assignment_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    if classroom.teacher_id == user.user_id {
+        return HttpResponse::BadRequest().json(json!({"message": "任课教师不需要完成作业"}));
+    }
+
+    match get_submission(&pool, assignment.assignment_id, user.user_id) {
+        Ok(submission) => return HttpResponse::Ok().json(json!({
+            "message": "作业已开始",
+            "chat_id": submission.chat_id.to_string(),
+            "submission": submission_json(&assignment, &submission)
+        })),
+        Err(diesel::result::Error::NotFound) => {}
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    }
+
+    let new_chat = NewChat::new(user.user_id, &assignment.title).with_classroom(Some(classroom.classroom_id));
+    let seed_message = NewMessage::new(new_chat.chat_id, &String::from("user"), &assignment.seed_prompt());
+    let submission = AssignmentSubmission::new(assignment.assignment_id, user.user_id, new_chat.chat_id);
+
+    match start_assignment(&pool, &new_chat, &seed_message, &submission) {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "开始作业成功",
+            "chat_id": new_chat.chat_id.to_string(),
+            "submission": submission_json(&assignment, &submission)
+        })),
+        Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+            HttpResponse::Conflict().json(json!({"message": "作业已开始"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/assignment/{assignment_id}/submit
+pub async fn assignment_submit(
+    user: SessionUser,
+    assignment_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let (assignment, _) = match visible_assignment(&pool, user.user_id, &assignment_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match update_submission_status(&pool, assignment.assignment_id, user.user_id, SUBMISSION_IN_PROGRESS, SUBMISSION_SUBMITTED, None) {
+        Ok(0) => {}
+        Ok(_) => {
+            return match get_submission(&pool, assignment.assignment_id, user.user_id) {
+                Ok(submission) => HttpResponse::Ok().json(json!({
+                    "message": "作业已提交",
+                    "submission": submission_json(&assignment, &submission)
+                })),
+                Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+            };
+        }
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    }
+
+    match get_submission(&pool, assignment.assignment_id, user.user_id) {
+        Ok(_) => HttpResponse::Conflict().json(json!({"message": "作业已提交，不能重复提交"})),
+        Err(diesel::result::Error::NotFound) => HttpResponse::BadRequest().json(json!({"message": "尚未开始该作业"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/assignment/{assignment_id}/submissions，列出班级所有学生的完成情况
+pub async fn assignment_submissions(
+    user: SessionUser,
+    assignment_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let assignment = match taught_assignment(&pool, user.user_id, &assignment_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let members = match get_classroom_members(&pool, assignment.classroom_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let submissions = match get_submissions_by_assignment_id(&pool, assignment.assignment_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let chat_ids: Vec<Uuid> = submissions.iter().map(|s| s.chat_id).collect();
+    let activity = match get_chat_activity(&pool, &chat_ids) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let students_json: Vec<Value> = members.iter().map(|(member, username)| {
+        let submission = submissions.iter().find(|s| s.user_id == member.user_id);
+        let mut student_json = match submission {
+            Some(submission) => submission_json(&assignment, submission),
+            None => json!({"user_id": member.user_id.to_string(), "status": "not_started"}),
+        };
+        student_json["username"] = json!(username);
+        if let Some(item) = submission.and_then(|s| activity.iter().find(|a| a.chat_id == s.chat_id)) {
+            student_json["message_count"] = json!(item.message_count);
+            student_json["last_active_at"] = json!(item.last_active_at.map(|t| t.to_string()));
+        }
+        student_json
+    }).collect();
+
+    let count_status = |status: &str| submissions.iter().filter(|s| s.status == status).count();
+
+    HttpResponse::Ok().json(json!({
+        "students": students_json,
+        "summary": {
+            "total": members.len(),
+            "in_progress": count_status(SUBMISSION_IN_PROGRESS),
+            "submitted": count_status(SUBMISSION_SUBMITTED),
+            "reviewed": count_status(SUBMISSION_REVIEWED)
+        },
+        "status": "200",
+        "message": "查询作业完成情况成功"
+    }))
+}
+
+//  /v1/assignment/{assignment_id}/submissions/{user_id}/messages
+//  提交后的作业对话对教师可见；提交之前需要学生在班级中授权查看对话
+pub async fn assignment_submission_messages(
+    user: SessionUser,
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let (assignment_id, student_id) = path.into_inner();
+
+    let assignment = match taught_assignment(&pool, user.user_id, &assignment_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
 
+    let student_uuid = match parse_user_id(&student_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let submission = match get_submission(&pool, assignment.assignment_id, student_uuid) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => return HttpResponse::NotFound().json(json!({"message": "该学生尚未开始作业"})),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if submission.status == SUBMISSION_IN_PROGRESS {
+        match get_classroom_member(&pool, assignment.classroom_id, student_uuid) {
+            Ok(member) if member.share_chats => {}
+            Ok(_) | Err(diesel::result::Error::NotFound) => {
+                return HttpResponse::Forbidden().json(json!({"message": "学生提交作业后才能查看对话"}));
+            }
+            Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+        }
+    }
+
+    let msgs = match get_all_messages_by_chat_id(&pool, submission.chat_id, student_uuid) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let msgs_json: Vec<Value> = msgs.iter().map(message_json).collect();
+
+    HttpResponse::Ok().json(json!({
+        "submission": submission_json(&assignment, &submission),
+        "chats": msgs_json,
+        "status": "200",
+        "message": "查询作业对话成功"
+    }))
+}
+
+//  /v1/assignment/{assignment_id}/submissions/{user_id}/review
+pub async fn assignment_review(
+    user: SessionUser,
+    path: web::Path<(String, String)>,
+    pool: web::Data<DbPool>,
+    payload: web::Json<ReviewSubmissionPayload>,
+) -> impl Responder {
+    let (assignment_id, student_id) = path.into_inner();
+
+    let assignment = match taught_assignment(&pool, user.user_id, &assignment_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let student_uuid = match parse_user_id(&student_id) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let feedback = payload.feedback.as_deref().map(str::trim).filter(|f| !f.is_empty());
+
+    match update_submission_status(&pool, assignment.assignment_id, student_uuid, SUBMISSION_SUBMITTED, SUBMISSION_REVIEWED, feedback) {
+        Ok(0) => HttpResponse::Conflict().json(json!({"message": "只能批改已提交的作业"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "批改完成"})),
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+
+//  /v1/account/export，以 JSON 附件导出该用户的全部个人数据，不包含密码哈希、TOTP 密钥等凭据
+pub async fn account_export(
+    req: HttpRequest,
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+) -> impl Responder {
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    let export = match build_account_export(&pool, &account) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    audit::record(&pool, &req, audit::ACCOUNT_EXPORTED, Some(user.user_id), None, json!({}));
+
+    let filename = format!("math-rag-export-{}-{}.json", account.username, now().format("%Y%m%d"));
+
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)))
+        .json(export)
+}
+
+fn build_account_export(pool: &DbPool, account: &User) -> Result<Value, diesel::result::Error> {
+    let chats = get_all_chats_by_user_id(pool, account.user_id)?;
+    let messages = get_all_messages_by_user_id(pool, account.user_id)?;
+    let sessions = get_sessions_by_user_id(pool, account.user_id)?;
+    let api_keys = get_api_keys_by_user_id(pool, account.user_id)?;
+    let identities = get_identities_by_user_id(pool, account.user_id)?;
+    let ocr_records = get_ocr_records_by_user_id(pool, account.user_id)?;
+    let teaching = get_classrooms_by_teacher_id(pool, account.user_id)?;
+    let joined = get_joined_classrooms(pool, account.user_id)?;
+    let submissions = get_all_submissions_by_user_id(pool, account.user_id)?;
+    let folders = get_folders_by_user_id(pool, account.user_id)?;
+    let tags = get_tags_by_user_id(pool, account.user_id)?;
+    let chat_uuids: Vec<Uuid> = chats.iter().map(|chat| chat.chat_id).collect();
+    let chat_tags = get_tags_for_chats(pool, &chat_uuids)?;
+
+    let chats_json: Vec<Value> = chats.iter().map(|chat| json!({
+        "chat_id": chat.chat_id.to_string(),
+        "title": chat.title,
+        "created_at": chat.created_at.to_string(),
+        "deleted_at": chat.deleted_at.map(|t| t.to_string()),
+        "classroom_id": chat.classroom_id.map(|id| id.to_string()),
+        "folder_id": chat.folder_id.map(|id| id.to_string()),
+        "forked_from_chat_id": chat.forked_from_chat_id.map(|id| id.to_string()),
+        "forked_from_message_id": chat.forked_from_message_id.map(|id| id.to_string()),
+        "tags": chat_tags.iter()
+            .filter(|(chat_uuid, _)| *chat_uuid == chat.chat_id)
+            .map(|(_, tag)| tag.name.clone())
+            .collect::<Vec<String>>(),
+        "messages": messages.iter()
+            .filter(|msg| msg.chat_id == chat.chat_id)
+            .map(message_json)
+            .collect::<Vec<Value>>()
+    })).collect();
+
+    let sessions_json: Vec<Value> = sessions.iter().map(|session| json!({
+        "session_id": session.session_id.to_string(),
+        "device_name": session.device_name,
+        "ip_address": session.ip_address,
+        "user_agent": session.user_agent,
+        "created_at": session.created_at.map(|t| t.to_string()),
+        "last_seen_at": session.last_seen_at.map(|t| t.to_string()),
+        "expires_at": session.expires_at.to_string()
+    })).collect();
+
+    let identities_json: Vec<Value> = identities.iter().map(|identity| json!({
+        "provider": identity.provider,
+        "subject": identity.subject,
+        "email": identity.email,
+        "created_at": identity.created_at.map(|t| t.to_string()),
+        "last_login_at": identity.last_login_at.map(|t| t.to_string())
+    })).collect();
+
+    let ocr_json: Vec<Value> = ocr_records.iter().map(|record| json!({
+        "content": record.content,
+        "created_at": record.created_at.map(|t| t.to_string())
+    })).collect();
+
+    let mut classrooms_json: Vec<Value> = teaching.iter().map(|classroom| {
+        let mut classroom_json = classroom_json(classroom, true);
+        classroom_json["membership"] = json!("teacher");
+        classroom_json
+    }).collect();
+    classrooms_json.extend(joined.iter().map(|(classroom, member)| {
+        let mut classroom_json = classroom_json(classroom, false);
+        classroom_json["membership"] = json!("student");
+        classroom_json["share_chats"] = json!(member.share_chats);
+        classroom_json["joined_at"] = json!(member.joined_at.map(|t| t.to_string()));
+        classroom_json
+    }));
+
+    let submissions_json: Vec<Value> = submissions.iter().map(|submission| json!({
+        "assignment_id": submission.assignment_id.to_string(),
+        "chat_id": submission.chat_id.to_string(),
+        "status": submission.status,
+        "started_at": submission.started_at.map(|t| t.to_string()),
+        "submitted_at": submission.submitted_at.map(|t| t.to_string()),
+        "reviewed_at": submission.reviewed_at.map(|t| t.to_string()),
+        "feedback": submission.feedback
+    })).collect();
+
+    Ok(json!({
+        "exported_at": now().to_string(),
+        "profile": {
+            "user_id": account.user_id.to_string(),
+            "username": account.username,
+            "email": account.email,
+            "role": account.role,
+            "created_at": account.created_at.map(|t| t.to_string()),
+            "email_verified_at": account.email_verified_at.map(|t| t.to_string()),
+            "totp_enabled_at": account.totp_enabled_at.map(|t| t.to_string()),
+            "deletion_scheduled_at": account.deletion_scheduled_at.map(|t| t.to_string())
+        },
+        "sessions": sessions_json,
+        "api_keys": api_keys.iter().map(api_key_json).collect::<Vec<Value>>(),
+        "identities": identities_json,
+        "classrooms": classrooms_json,
+        "assignment_submissions": submissions_json,
+        "folders": folders.iter().map(folder_json).collect::<Vec<Value>>(),
+        "tags": tags.iter().map(|(tag, _)| tag.name.clone()).collect::<Vec<String>>(),
+        "chats": chats_json,
+        "ocr_records": ocr_json
+    }))
+}
+
+//  DELETE /v1/account，重新认证后进入注销宽限期，到期由后台任务彻底删除。
+//  通过第三方登录注册的账号需要先用“忘记密码”设置密码
+pub async fn account_delete(
+    req: HttpRequest,
+    user: SessionUser,
+    pool: web::Data<DbPool>,
+    payload: web::Json<DeleteAccountPayload>,
+) -> impl Responder {
+    let account = match get_user_by_id(&pool, user.user_id) {
+        Ok(data) => data,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if !verify(&payload.password, &account.password_hash).unwrap_or(false) {
+        return HttpResponse::Unauthorized().json(json!({"message": "密码不正确"}));
+    }
+
+    if account.totp_enabled_at.is_some() {
+        match verify_second_factor(&pool, &account, payload.code.as_deref(), payload.recovery_code.as_deref()) {
+            Ok(true) => {}
+            Ok(false) => return HttpResponse::Unauthorized().json(json!({"message": "验证码不正确"})),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+        }
+    }
+
+    let scheduled_at = now() + chrono::Duration::days(ACCOUNT_DELETION_GRACE_DAYS);
+
+    match schedule_account_deletion(&pool, account.user_id, scheduled_at, now()) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::ACCOUNT_DELETION_REQUESTED, Some(account.user_id), None, json!({"deletion_scheduled_at": scheduled_at.to_string()}));
+            HttpResponse::Ok().json(json!({
+                "message": "账号已申请注销，宽限期内可以恢复",
+                "deletion_scheduled_at": scheduled_at.to_string()
+            }))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+//  /v1/account/restore，宽限期内凭用户名和密码撤销注销，与登录共用失败计数
+pub async fn account_restore(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    payload: web::Json<RestoreAccountPayload>,
+) -> impl Responder {
+    let throttle_keys = login_throttle_keys(&req, &payload.username);
+    if let Some(response) = check_login_throttle(&pool, &req, &payload.username, &throttle_keys) {
+        return response;
+    }
+
+    let account = match get_user_by_username(&pool, &payload.username) {
+        Ok(data) => data,
+        Err(diesel::result::Error::NotFound) => {
+            let _ = verify(&payload.password, dummy_password_hash());
+            return login_failed(&pool, &req, &payload.username, &throttle_keys);
+        }
+        Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()}))
+    };
+
+    if !verify(&payload.password, &account.password_hash).unwrap_or(false) {
+        return login_failed(&pool, &req, &payload.username, &throttle_keys);
+    }
+
+    if account.totp_enabled_at.is_some() {
+        match verify_second_factor(&pool, &account, payload.code.as_deref(), payload.recovery_code.as_deref()) {
+            Ok(true) => {}
+            Ok(false) => return login_failed(&pool, &req, &payload.username, &throttle_keys),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+        }
+    }
+
+    let _ = clear_login_throttle(&pool, &LoginThrottle::user_key(&account.username));
+
+    if account.deletion_scheduled_at.is_none() {
+        return HttpResponse::BadRequest().json(json!({"message": "账号未申请注销"}));
+    }
+
+    match cancel_account_deletion(&pool, account.user_id) {
+        Ok(_) => {
+            audit::record(&pool, &req, audit::ACCOUNT_RESTORED, Some(account.user_id), None, json!({}));
+            HttpResponse::Ok().json(json!({"message": "账号已恢复，请重新登录"}))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({"message": err.to_string()})),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use crate::test_support::{create_chat, create_user, delete_user, test_pool};
+
+    fn login_token(pool: &DbPool, user_id: Uuid) -> String {
+        let client = ClientInfo { device_name: None, ip_address: None, user_agent: None };
+        let (new_session, jwt_token, _) = build_session(user_id, None, &client).unwrap();
+        add_new_session(pool, &new_session).unwrap();
+        jwt_token
+    }
+
+    #[actix_web::test]
+    #[ignore = "需要 TEST_DATABASE_URL"]
+    async fn chat_endpoints_return_404_for_other_users_chat() {
+        let pool = test_pool();
+        let owner = create_user(&pool);
+        let other = create_user(&pool);
+        let chat_uuid = create_chat(&pool, owner);
+        let owner_token = login_token(&pool, owner);
+        let other_token = login_token(&pool, other);
+
+        let app = test::init_service(App::new().app_data(Data::new(pool.clone())).configure(crate::config)).await;
+
+        let requests = [
+            test::TestRequest::get().uri(&format!("/v1/chat/{}", chat_uuid)),
+            test::TestRequest::patch().uri(&format!("/v1/chat/{}", chat_uuid)).set_json(json!({"title": "改名"})),
+            test::TestRequest::delete().uri(&format!("/v1/chat/{}", chat_uuid)),
+            test::TestRequest::delete().uri(&format!("/v1/chat/trash/{}", chat_uuid)),
+            test::TestRequest::post().uri("/v1/chat/stream").set_json(json!({"prompt": "你好", "chat_id": chat_uuid.to_string()})),
+        ];
+        for request in requests {
+            let request = request
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", other_token)))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), 404);
+        }
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/v1/chat/{}", chat_uuid))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", owner_token)))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["chats"].as_array().unwrap().len(), 1);
+
+        delete_user(&pool, owner);
+        delete_user(&pool, other);
+    }
+
+    #[actix_web::test]
+    #[ignore = "需要 TEST_DATABASE_URL"]
+    async fn challenge_token_cannot_be_replayed() {
+        let pool = test_pool();
+        let user_id = create_user(&pool);
+        set_pending_totp_secret(&pool, user_id, &generate_totp_secret()).unwrap();
+        let codes = generate_recovery_codes(2);
+        let code_hashes: Vec<NewRecoveryCode> = codes.iter()
+            .map(|code| NewRecoveryCode::new(user_id, &hash_token(&normalize_recovery_code(code))))
+            .collect();
+        enable_totp(&pool, user_id, 0, &code_hashes).unwrap();
+
+        let app = test::init_service(App::new().app_data(Data::new(pool.clone())).configure(crate::config)).await;
+        let challenge_token = generate_challenge_jwt(&user_id).unwrap();
+
+        let request = test::TestRequest::post()
+            .uri("/v1/auth/login/totp")
+            .set_json(json!({"challenge_token": challenge_token, "recovery_code": codes[0]}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), 200);
+
+        // 换一个有效的恢复码也不能再次使用同一个挑战令牌
+        let request = test::TestRequest::post()
+            .uri("/v1/auth/login/totp")
+            .set_json(json!({"challenge_token": challenge_token, "recovery_code": codes[1]}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), 401);
+
+        delete_user(&pool, user_id);
+    }
+
+    // 完整走一遍第三方登录：授权地址、模拟签发者同意授权、回调换取会话
+    #[actix_web::test]
+    #[ignore = "需要 TEST_DATABASE_URL"]
+    async fn oidc_login_against_mock_issuer() {
+        let pool = test_pool();
+        let mock = oidc::mock::MockIssuer::start("/realms/school", "math-rag");
+        env::set_var("OIDC_PROVIDERS", "mock");
+        env::set_var("OIDC_MOCK_ISSUER", &mock.issuer);
+        env::set_var("OIDC_MOCK_CLIENT_ID", &mock.client_id);
+        env::set_var("OIDC_MOCK_REDIRECT_URI", "http://localhost:3000/callback");
+
+        let app = test::init_service(App::new().app_data(Data::new(pool.clone())).configure(crate::config)).await;
+
+        let request = test::TestRequest::get().uri("/v1/auth/oidc/mock/authorize").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        let state_cookie = response.response().cookies().find(|c| c.name() == OIDC_STATE_COOKIE).unwrap().into_owned();
+        let body: Value = test::read_body_json(response).await;
+
+        let subject = generate_uuid().to_string();
+        let email = format!("{}@example.com", &subject[..8]);
+        let (code, state) = mock.authorize(body["authorization_url"].as_str().unwrap(), &subject, &email);
+        assert_eq!(state_cookie.value(), state);
+
+        // 没有 state Cookie 的回调被拒绝，且不会消耗登录请求
+        let request = test::TestRequest::post()
+            .uri("/v1/auth/oidc/mock/callback")
+            .set_json(json!({"code": code, "state": state}))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), 400);
+
+        let request = test::TestRequest::post()
+            .uri("/v1/auth/oidc/mock/callback")
+            .cookie(state_cookie)
+            .set_json(json!({"code": code, "state": state}))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        let body: Value = test::read_body_json(response).await;
+        assert!(body["token"].is_string());
+
+        let user = get_user_by_identity(&pool, "mock", &subject).unwrap();
+        assert_eq!(user.email, email);
+        assert!(user.email_verified_at.is_some());
+
+        delete_user(&pool, user.user_id);
+    }
+
+    #[actix_web::test]
+    #[ignore = "需要 TEST_DATABASE_URL"]
+    async fn chat_endpoints_require_login() {
+        let pool = test_pool();
+        let owner = create_user(&pool);
+        let chat_uuid = create_chat(&pool, owner);
+
+        let app = test::init_service(App::new().app_data(Data::new(pool.clone())).configure(crate::config)).await;
+
+        let request = test::TestRequest::get().uri(&format!("/v1/chat/{}", chat_uuid)).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 401);
+
+        delete_user(&pool, owner);
+    }
 }
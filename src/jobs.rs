@@ -0,0 +1,35 @@
+use std::env;
+use std::thread;
+use std::time::Duration;
+use crate::database::{purge_deleted_accounts, purge_trashed_chats, DbPool};
+use crate::utils::{chat_trash_retention_days, now};
+
+/// 后台任务的默认执行间隔（秒）
+const DEFAULT_JOB_INTERVAL_SECONDS: u64 = 3600;
+
+/// 启动后台清理线程，按 `BACKGROUND_JOB_INTERVAL_SECONDS` 的间隔循环执行
+pub fn spawn_background_jobs(pool: DbPool) {
+    let interval = env::var("BACKGROUND_JOB_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_JOB_INTERVAL_SECONDS);
+
+    thread::spawn(move || loop {
+        run_jobs(&pool);
+        thread::sleep(Duration::from_secs(interval));
+    });
+}
+
+fn run_jobs(pool: &DbPool) {
+    match purge_deleted_accounts(pool, now()) {
+        Ok(0) => {}
+        Ok(count) => println!("已彻底删除 {} 个注销账号", count),
+        Err(err) => println!("清理注销账号失败: {}", err),
+    }
+
+    match purge_trashed_chats(pool, now() - chrono::Duration::days(chat_trash_retention_days())) {
+        Ok(0) => {}
+        Ok(count) => println!("已彻底删除 {} 个超过保留期的回收站对话", count),
+        Err(err) => println!("清理回收站失败: {}", err),
+    }
+}
@@ -0,0 +1,43 @@
+use std::env;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Arc;
+use dotenv::dotenv;
+use crate::utils::now;
+
+pub type MailResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// 邮件发送接口，处理函数通过 `web::Data<dyn Mailer>` 取用，便于替换为 SMTP 等实现
+pub trait Mailer: Send + Sync {
+    fn send(&self, to: &str, subject: &str, body: &str) -> MailResult;
+}
+
+/// 本地开发用：把邮件追加写入 `MAIL_LOG_PATH` 指定的文件，未配置时直接打印到标准输出
+pub struct LogMailer {
+    path: Option<String>,
+}
+
+impl Mailer for LogMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> MailResult {
+        let mail = format!("[{}] To: {}\nSubject: {}\n\n{}\n\n", now(), to, subject, body);
+
+        match &self.path {
+            Some(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                file.write_all(mail.as_bytes())?;
+            }
+            None => println!("{}", mail),
+        }
+        Ok(())
+    }
+}
+
+pub fn init_mailer() -> Arc<dyn Mailer> {
+    dotenv().ok();
+
+    match env::var("MAILER").unwrap_or_else(|_| String::from("log")).as_str() {
+        "log" => Arc::new(LogMailer { path: env::var("MAIL_LOG_PATH").ok() }),
+        other => panic!("不支持的 MAILER: {}", other),
+    }
+}
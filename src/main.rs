@@ -2,8 +2,11 @@ use actix_web::{web, HttpServer, App};
 use actix_web::middleware::from_fn;
 use database::init_pool;
 use handlers::*;
-use middleware::auth_middleware;
+use middleware::{auth_middleware, require_permission};
+use rbac::Permission;
 use actix_cors::Cors;
+use mailer::init_mailer;
+use jobs::spawn_background_jobs;
 
 mod database;
 mod schema;
@@ -12,22 +15,135 @@ mod models;
 mod utils;
 mod middleware;
 mod xunfei_ocr;
+mod mailer;
+mod totp;
+mod oidc;
+mod rbac;
+mod jobs;
+mod audit;
+#[cfg(test)]
+mod test_support;
 
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/v1/auth")
+            .wrap(from_fn(auth_middleware))
             .route("/register", web::post().to(handle_register))
             .route("/login", web::post().to(handle_login))
+            .route("/login/totp", web::post().to(handle_login_totp))
             .route("/logout", web::post().to(handle_logout))
+            .route("/refresh", web::post().to(handle_refresh))
+            .route("/oidc/providers", web::get().to(oidc_providers))
+            .route("/oidc/{provider}/authorize", web::get().to(oidc_authorize))
+            .route("/oidc/{provider}/callback", web::post().to(oidc_callback))
+            .route("/email/verify", web::post().to(email_verify))
+            .route("/email/resend", web::post().to(email_verify_resend))
+            .route("/password", web::post().to(password_change))
+            .route("/password/forgot", web::post().to(password_forgot))
+            .route("/password/reset", web::post().to(password_reset))
+            .route("/totp/setup", web::post().to(totp_setup))
+            .route("/totp/confirm", web::post().to(totp_confirm))
+            .route("/totp/recovery-codes", web::post().to(totp_recovery_codes))
+            .route("/totp/disable", web::post().to(totp_disable))
+            .route("/api-keys", web::get().to(api_key_list))
+            .route("/api-keys", web::post().to(api_key_new))
+            .route("/api-keys/{api_key_id}", web::delete().to(api_key_revoke))
+            .route("/sessions", web::get().to(session_list))
+            .route("/sessions", web::delete().to(session_revoke_all))
+            .route("/sessions/{session_id}", web::get().to(session_detail))
+            .route("/sessions/{session_id}", web::delete().to(session_revoke))
+    );
+    cfg.service(
+        web::scope("/v1/account")
+            .wrap(from_fn(auth_middleware))
+            .route("", web::delete().to(account_delete))
+            .route("/export", web::get().to(account_export))
+            .route("/restore", web::post().to(account_restore))
+    );
+    cfg.service(
+        web::scope("/v1/admin")
+            .wrap(from_fn(|req, next| require_permission(Permission::ManageUsers, req, next)))
+            .wrap(from_fn(auth_middleware))
+            .route("/users", web::get().to(admin_user_list))
+            .route("/users/{user_id}", web::get().to(admin_user_detail))
+            .route("/users/{user_id}/disable", web::post().to(admin_user_disable))
+            .route("/users/{user_id}/enable", web::post().to(admin_user_enable))
+            .route("/users/{user_id}/role", web::put().to(admin_user_role))
+            .route("/users/{user_id}/password", web::post().to(admin_user_password_reset))
+            .route("/users/{user_id}/unlock", web::post().to(admin_unlock_user))
+            .route("/users/{user_id}/usage", web::get().to(admin_user_usage))
+            .route("/usage", web::get().to(admin_usage))
+            .service(
+                web::resource("/audit-events")
+                    .wrap(from_fn(|req, next| require_permission(Permission::ViewAuditLog, req, next)))
+                    .route(web::get().to(admin_audit_events))
+            )
+    );
+    cfg.service(
+        web::scope("/v1/classroom")
+            .wrap(from_fn(auth_middleware))
+            .route("/new", web::post().to(classroom_new))
+            .route("/list", web::get().to(classroom_list))
+            .route("/join", web::post().to(classroom_join))
+            .route("/{classroom_id}", web::get().to(classroom_detail))
+            .route("/{classroom_id}", web::patch().to(classroom_update))
+            .route("/{classroom_id}", web::delete().to(classroom_delete))
+            .route("/{classroom_id}/invite-code", web::post().to(classroom_invite_reset))
+            .route("/{classroom_id}/leave", web::post().to(classroom_leave))
+            .route("/{classroom_id}/consent", web::put().to(classroom_consent))
+            .route("/{classroom_id}/members", web::get().to(classroom_members))
+            .route("/{classroom_id}/members/{user_id}", web::delete().to(classroom_member_remove))
+            .route("/{classroom_id}/members/{user_id}/chats", web::get().to(classroom_member_chats))
+    );
+    cfg.service(
+        web::scope("/v1/assignment")
+            .wrap(from_fn(auth_middleware))
+            .route("/new", web::post().to(assignment_new))
+            .route("/list", web::get().to(assignment_list))
+            .route("/{assignment_id}", web::get().to(assignment_detail))
+            .route("/{assignment_id}", web::delete().to(assignment_delete))
+            .route("/{assignment_id}/start", web::post().to(assignment_start))
+            .route("/{assignment_id}/submit", web::post().to(assignment_submit))
+            .route("/{assignment_id}/submissions", web::get().to(assignment_submissions))
+            .route("/{assignment_id}/submissions/{user_id}/messages", web::get().to(assignment_submission_messages))
+            .route("/{assignment_id}/submissions/{user_id}/review", web::post().to(assignment_review))
+    );
+    cfg.service(
+        web::scope("/v1/folder")
+            .wrap(from_fn(auth_middleware))
+            .route("/new", web::post().to(folder_new))
+            .route("/list", web::get().to(folder_list))
+            .route("/{folder_id}", web::patch().to(folder_update))
+            .route("/{folder_id}", web::delete().to(folder_delete))
+    );
+    cfg.service(
+        web::scope("/v1/tag")
+            .wrap(from_fn(auth_middleware))
+            .route("/new", web::post().to(tag_new))
+            .route("/list", web::get().to(tag_list))
+            .route("/{tag_id}", web::patch().to(tag_update))
+            .route("/{tag_id}", web::delete().to(tag_delete))
     );
     cfg.service(
         web::scope("/v1/chat")
             .wrap(from_fn(auth_middleware))
             .route("/new", web::post().to(chat_new))
             .route("/history", web::get().to(chat_history))
+            .route("/search", web::get().to(chat_search))
+            .route("/trash", web::get().to(chat_trash))
+            .route("/trash/{chat_id}", web::delete().to(chat_purge))
+            .route("/trash/{chat_id}/restore", web::post().to(chat_restore))
+            .route("/bulk/move", web::post().to(chat_bulk_move))
+            .route("/bulk/tag", web::post().to(chat_bulk_tag))
             .route("/{chat_id}", web::get().to(chat_content))
+            .route("/{chat_id}", web::patch().to(chat_update))
             .route("/{chat_id}", web::delete().to(chat_delete))
+            .route("/{chat_id}/messages/{message_id}/edit", web::post().to(chat_message_edit))
+            .route("/{chat_id}/messages/{message_id}/regenerate", web::post().to(chat_message_regenerate))
+            .route("/{chat_id}/messages/{message_id}/alternatives", web::get().to(chat_message_alternatives))
+            .route("/{chat_id}/messages/{message_id}/activate", web::post().to(chat_message_activate))
+            .route("/{chat_id}/messages/{message_id}/fork", web::post().to(chat_message_fork))
             .route("/ocr", web::post().to(ocr_handle))
             .route("/stream", web::post().to(proxy_stream))
     );
@@ -37,6 +153,8 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 async fn main() -> std::io::Result<()> {
     println!("database init");
     let pool_data = init_pool();
+    let mailer = init_mailer();
+    spawn_background_jobs(pool_data.clone());
     println!("Server started at http://127.0.0.1:8080");
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -47,6 +165,7 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(cors)
             .app_data(web::Data::new(pool_data.clone()))
+            .app_data(web::Data::from(mailer.clone()))
             .configure(config)
     })
     .bind("127.0.0.1:8080")?
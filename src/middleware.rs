@@ -1,18 +1,103 @@
 use actix_web::middleware::Next;
-use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::{web, Error, HttpMessage};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
 use actix_web::body::MessageBody;
-use chrono::Utc;
-use crate::utils::decode_jwt;
-use crate::database::{get_session_by_session_id, DbPool};
+use futures::future::{ready, Ready};
+use serde_json::json;
+use uuid::Uuid;
+use crate::models::{ApiKey, Session};
+use crate::rbac::{Permission, Role};
+use crate::audit;
+use crate::utils::{decode_jwt, extract_token, hash_token, now};
+use crate::database::{get_session_by_session_id, touch_session, get_api_key_by_prefix, touch_api_key, get_active_user_role, DbPool};
 
-// 统一的失败处理
+/// 请求携带的凭据：登录会话拥有全部权限，API 密钥只拥有创建时申请的权限范围
+#[derive(Clone)]
+pub enum Credential {
+    Session(Session),
+    ApiKey { scopes: Vec<String> },
+}
+
+/// 通过认证的用户，由 `auth_middleware` 写入请求扩展。
+/// 作为处理函数参数使用时，未登录的请求会直接返回 401。
+#[derive(Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub credential: Credential,
+}
+
+impl AuthenticatedUser {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match &self.credential {
+            Credential::Session(_) => true,
+            Credential::ApiKey { scopes, .. } => scopes.iter().any(|s| s == scope),
+        }
+    }
+
+    /// 权限不足时返回 403 响应，处理函数直接将其返回
+    pub fn require_scope(&self, scope: &str) -> Result<(), HttpResponse> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(HttpResponse::Forbidden().json(json!({"message": format!("API 密钥缺少权限: {}", scope)})))
+        }
+    }
+}
+
+fn unauthorized() -> Error {
+    InternalError::from_response(
+        "用户未登录",
+        HttpResponse::Unauthorized().json(json!({"message": "用户未登录"})),
+    ).into()
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<AuthenticatedUser>() {
+            Some(user) => ready(Ok(user.clone())),
+            None => ready(Err(unauthorized())),
+        }
+    }
+}
+
+/// 只接受登录会话的用户。账号、会话和密钥管理等接口使用它，API 密钥访问时返回 403
+pub struct SessionUser {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub session: Session,
+}
+
+impl FromRequest for SessionUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<AuthenticatedUser>() {
+            Some(AuthenticatedUser { user_id, role, credential: Credential::Session(session) }) => ready(Ok(SessionUser {
+                user_id: *user_id,
+                role: *role,
+                session: session.clone(),
+            })),
+            Some(_) => ready(Err(InternalError::from_response(
+                "API 密钥无权访问",
+                HttpResponse::Forbidden().json(json!({"message": "该接口需要登录后访问，不支持 API 密钥"})),
+            ).into())),
+            None => ready(Err(unauthorized())),
+        }
+    }
+}
+
+// 统一的失败处理：不写入 AuthenticatedUser，由提取器决定是否拒绝
 async fn fail_auth<B>(req: ServiceRequest, next: Next<B>) -> Result<ServiceResponse<B>, Error>
 where
     B: MessageBody,
 {
-    req.extensions_mut().insert(false);
-    Ok(next.call(req).await?)
+    next.call(req).await
 }
 
 pub async fn auth_middleware(
@@ -22,11 +107,22 @@ pub async fn auth_middleware(
     let auth_header = req.headers().get("Authorization");
 
     let token = match auth_header.and_then(|h| h.to_str().ok()) {
-        Some(t) => t.to_string(),
+        Some(t) => extract_token(t).to_string(),
         None => return fail_auth(req, next).await,
     };
 
-    let session_uuid = match decode_jwt(&token) {
+    if let Some(key_prefix) = ApiKey::parse_prefix(&token) {
+        let key_prefix = key_prefix.to_string();
+        return api_key_auth(req, next, &token, &key_prefix).await;
+    }
+
+    // 签名、过期时间等校验不通过的令牌直接拒绝，不再查询数据库
+    let claims = match decode_jwt(&token) {
+        Ok(claims) => claims,
+        Err(_) => return fail_auth(req, next).await,
+    };
+
+    let session_uuid = match claims.session_uuid() {
         Ok(uuid) => uuid,
         Err(_) => return fail_auth(req, next).await,
     };
@@ -36,22 +132,127 @@ pub async fn auth_middleware(
         None => return fail_auth(req, next).await,
     };
 
+    // 以下情况签名有效但会话不可用，多为登出或被吊销后继续使用的令牌，记录审计日志
     let session = match get_session_by_session_id(pool, session_uuid) {
         Ok(session) => session,
-        Err(_) => return fail_auth(req, next).await,
+        Err(_) => {
+            audit::record(pool, req.request(), audit::TOKEN_REJECTED, claims.user_uuid().ok(), None, json!({"reason": "session_not_found"}));
+            return fail_auth(req, next).await;
+        }
     };
 
-    if session.token != token {
+    let rejected = if session.token != token || claims.sub != session.user_id.to_string() {
+        Some("token_mismatch")
+    } else if session.expires_at < now() {
+        Some("session_revoked")
+    } else {
+        None
+    };
+    if let Some(reason) = rejected {
+        audit::record(pool, req.request(), audit::TOKEN_REJECTED, Some(session.user_id), Some(("session", &session.family_id.to_string())), json!({"reason": reason}));
         return fail_auth(req, next).await;
     }
 
-    if session.expires_at < Utc::now().naive_utc() {
-        return fail_auth(req, next).await;
-    }
+    let role = match active_user_role(pool, session.user_id) {
+        Some(role) => role,
+        None => {
+            audit::record(pool, req.request(), audit::TOKEN_REJECTED, Some(session.user_id), Some(("session", &session.family_id.to_string())), json!({"reason": "account_inactive"}));
+            return fail_auth(req, next).await;
+        }
+    };
 
+    let _ = touch_session(pool, session.session_id, now());
 
-    req.extensions_mut().insert(true);
-    req.extensions_mut().insert(session);
+    req.extensions_mut().insert(AuthenticatedUser {
+        user_id: session.user_id,
+        role,
+        credential: Credential::Session(session),
+    });
 
     next.call(req).await
-}
\ No newline at end of file
+}
+
+async fn api_key_auth<B>(req: ServiceRequest, next: Next<B>, token: &str, key_prefix: &str) -> Result<ServiceResponse<B>, Error>
+where
+    B: MessageBody,
+{
+    let pool = match req.app_data::<web::Data<DbPool>>() {
+        Some(pool) => pool,
+        None => return fail_auth(req, next).await,
+    };
+
+    let api_key = match get_api_key_by_prefix(pool, key_prefix) {
+        Ok(api_key) => api_key,
+        Err(_) => {
+            audit::record(pool, req.request(), audit::API_KEY_REJECTED, None, Some(("api_key_prefix", key_prefix)), json!({"reason": "not_found"}));
+            return fail_auth(req, next).await;
+        }
+    };
+
+    let rejected = if api_key.key_hash != hash_token(token) {
+        Some("secret_mismatch")
+    } else if api_key.revoked_at.is_some() {
+        Some("revoked")
+    } else if api_key.expires_at.is_some_and(|expires_at| expires_at < now()) {
+        Some("expired")
+    } else {
+        None
+    };
+
+    let role = match rejected {
+        Some(reason) => Err(reason),
+        None => active_user_role(pool, api_key.user_id).ok_or("account_inactive"),
+    };
+
+    let role = match role {
+        Ok(role) => role,
+        Err(reason) => {
+            audit::record(pool, req.request(), audit::API_KEY_REJECTED, Some(api_key.user_id), Some(("api_key", &api_key.api_key_id.to_string())), json!({"reason": reason}));
+            return fail_auth(req, next).await;
+        }
+    };
+
+    let _ = touch_api_key(pool, api_key.api_key_id, now());
+
+    req.extensions_mut().insert(AuthenticatedUser {
+        user_id: api_key.user_id,
+        role,
+        credential: Credential::ApiKey {
+            scopes: api_key.scope_list(),
+        },
+    });
+
+    next.call(req).await
+}
+
+// 读取用户角色，账号已停用、已申请注销或角色无法识别时返回 None
+fn active_user_role(pool: &DbPool, userid: Uuid) -> Option<Role> {
+    get_active_user_role(pool, userid).ok()?.parse().ok()
+}
+
+/// 作用域级别的权限守卫，需放在 `auth_middleware` 内层：
+/// `.wrap(from_fn(|req, next| require_permission(Permission::ManageUsers, req, next))).wrap(from_fn(auth_middleware))`。
+/// 未登录返回 401；API 密钥或角色不具备该权限返回 403
+pub async fn require_permission<B>(
+    permission: Permission,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error>
+where
+    B: MessageBody,
+{
+    let allowed = match req.extensions().get::<AuthenticatedUser>() {
+        Some(AuthenticatedUser { credential: Credential::Session(_), role, .. }) => role.has_permission(permission),
+        Some(_) => false,
+        None => return Err(unauthorized()),
+    };
+
+    if !allowed {
+        return Err(InternalError::from_response(
+            "无权访问",
+            HttpResponse::Forbidden().json(json!({"message": "无权访问"})),
+        ).into());
+    }
+
+    next.call(req).await
+}
@@ -2,109 +2,723 @@ use diesel::prelude::*;
 use chrono::{NaiveDateTime, Duration};
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
+use base64::{engine::general_purpose, Engine as _};
+use std::net::IpAddr;
 use crate::schema::*;
 
 use crate::utils::*;
 
 
-#[derive(Queryable)]
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = users)]
 pub struct User {
     pub user_id: Uuid,
     pub username: String,
     pub email: String,
     pub password_hash: String,
-    pub created_at: Option<NaiveDateTime>
+    pub created_at: Option<NaiveDateTime>,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<NaiveDateTime>,
+    pub role: String,
+    pub disabled_at: Option<NaiveDateTime>,
+    pub deletion_scheduled_at: Option<NaiveDateTime>,
 }
 
 
-#[derive(Queryable, Clone)]
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = user_sessions)]
 pub struct Session {
     pub session_id: Uuid,
     pub user_id: Uuid,
     pub token: String,
     pub created_at: Option<NaiveDateTime>,
-    pub expires_at: NaiveDateTime
+    pub expires_at: NaiveDateTime,
+    pub family_id: Uuid,
+    pub rotated_at: Option<NaiveDateTime>,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub last_seen_at: Option<NaiveDateTime>,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, QueryableByName)]
+#[diesel(table_name = messages)]
 pub struct Message{
     pub message_id: Uuid,
     pub chat_id: Uuid,
     pub role: String,
     pub content: String,
     pub timestamp: Option<NaiveDateTime>,
+    pub seq: i64,
+    pub parent_message_id: Option<Uuid>,
 }
 
-#[derive(Queryable)]
+/// 当前分支上的一条消息，`sibling_ids` 为同一父消息下的全部分支（含自身），按创建顺序排列
+#[derive(QueryableByName)]
+pub struct BranchMessage {
+    #[diesel(embed)]
+    pub message: Message,
+    #[diesel(sql_type = diesel::sql_types::Array<diesel::sql_types::Uuid>)]
+    pub sibling_ids: Vec<Uuid>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = chats)]
 pub struct Chat{
     pub chat_id: Uuid,
     pub user_id: Uuid,
     pub title: String,
+    pub created_at: NaiveDateTime,
+    pub classroom_id: Option<Uuid>,
+    pub last_message_at: NaiveDateTime,
+    pub pinned: bool,
+    pub archived: bool,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub folder_id: Option<Uuid>,
+    pub forked_from_chat_id: Option<Uuid>,
+    pub forked_from_message_id: Option<Uuid>,
+}
+
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub api_key_id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub key_hash: String,
+    pub scopes: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = login_throttles, treat_none_as_null = true)]
+pub struct LoginThrottle {
+    pub throttle_key: String,
+    pub failed_count: i32,
+    pub last_failed_at: Option<NaiveDateTime>,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = oidc_login_states)]
+pub struct OidcLoginState {
+    pub state: String,
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: String,
     pub created_at: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+}
+
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = audit_events)]
+pub struct AuditEvent {
+    pub event_id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub event_type: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = user_identities)]
+pub struct UserIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub last_login_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = ocr_records)]
+pub struct OcrRecord {
+    pub record_id: Uuid,
+    pub user_id: Uuid,
+    pub content: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = classrooms)]
+pub struct Classroom {
+    pub classroom_id: Uuid,
+    pub teacher_id: Uuid,
+    pub name: String,
+    pub invite_code: String,
+    pub system_prompt: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+/// 外层为 `None` 的字段不修改，`folder_id` 为 `Some(None)` 时移到顶层
+#[derive(AsChangeset)]
+#[diesel(table_name = chats)]
+pub struct ChatChangeset {
+    pub title: Option<String>,
+    pub pinned: Option<bool>,
+    pub archived: Option<bool>,
+    pub folder_id: Option<Option<Uuid>>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = folders)]
+pub struct Folder {
+    pub folder_id: Uuid,
+    pub user_id: Uuid,
+    pub parent_id: Option<Uuid>,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// 外层为 `None` 的字段不修改，`parent_id` 为 `Some(None)` 时移到顶层
+#[derive(AsChangeset)]
+#[diesel(table_name = folders)]
+pub struct FolderChangeset {
+    pub name: Option<String>,
+    pub parent_id: Option<Option<Uuid>>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = tags)]
+pub struct Tag {
+    pub tag_id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = chat_tags)]
+pub struct ChatTag {
+    pub chat_id: Uuid,
+    pub tag_id: Uuid,
+}
+
+/// 外层为 `None` 的字段不修改，`system_prompt` 为 `Some(None)` 时清空
+#[derive(AsChangeset)]
+#[diesel(table_name = classrooms)]
+pub struct ClassroomChangeset {
+    pub name: Option<String>,
+    pub system_prompt: Option<Option<String>>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = classroom_members)]
+pub struct ClassroomMember {
+    pub classroom_id: Uuid,
+    pub user_id: Uuid,
+    pub share_chats: bool,
+    pub joined_at: Option<NaiveDateTime>,
+}
+
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = assignments)]
+pub struct Assignment {
+    pub assignment_id: Uuid,
+    pub classroom_id: Uuid,
+    pub title: String,
+    pub problems: String,
+    pub problem_latex: Option<String>,
+    pub due_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = assignment_submissions)]
+pub struct AssignmentSubmission {
+    pub assignment_id: Uuid,
+    pub user_id: Uuid,
+    pub chat_id: Uuid,
+    pub status: String,
+    pub started_at: Option<NaiveDateTime>,
+    pub submitted_at: Option<NaiveDateTime>,
+    pub reviewed_at: Option<NaiveDateTime>,
+    pub feedback: Option<String>,
+}
+
+/// 单个对话的消息数和最近一条消息的时间
+#[derive(Queryable)]
+pub struct ChatActivity {
+    pub chat_id: Uuid,
+    pub message_count: i64,
+    pub last_active_at: Option<NaiveDateTime>,
+}
+
+/// 全文检索的一条命中，`message_id` 为空表示命中的是对话标题。
+/// `total` 为本次检索的命中总数，每行都相同
+#[derive(QueryableByName)]
+pub struct SearchHit {
+    #[diesel(sql_type = diesel::sql_types::Uuid)]
+    pub chat_id: Uuid,
+    #[diesel(sql_type = diesel::sql_types::Varchar)]
+    pub title: String,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Uuid>)]
+    pub message_id: Option<Uuid>,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Int8>)]
+    pub seq: Option<i64>,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Varchar>)]
+    pub role: Option<String>,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub snippet: String,
+    #[diesel(sql_type = diesel::sql_types::Float4)]
+    pub rank: f32,
+    #[diesel(sql_type = diesel::sql_types::Timestamp)]
+    pub matched_at: NaiveDateTime,
+    #[diesel(sql_type = diesel::sql_types::Int8)]
+    pub total: i64,
+}
+
+/// 班级成员在班级内的对话活跃情况，由聚合查询生成
+#[derive(Queryable)]
+pub struct MemberActivity {
+    pub user_id: Uuid,
+    pub chat_count: i64,
+    pub message_count: i64,
+    pub last_active_at: Option<NaiveDateTime>,
 }
 
 
 #[derive(Insertable)]
-#[table_name="users"]
+#[diesel(table_name = users)]
 pub struct NewUser {
-    user_id: Uuid,
+    pub user_id: Uuid,
     username: String,
     email: String,
     password_hash: String,
-    created_at: Option<NaiveDateTime>
+    created_at: Option<NaiveDateTime>,
+    email_verified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
-#[table_name="user_sessions"]
+#[diesel(table_name = user_sessions)]
 pub struct NewSession {
     session_id: Uuid,
     user_id: Uuid,
     token: String,
     created_at: Option<NaiveDateTime>,
-    expires_at: Option<NaiveDateTime>
+    expires_at: Option<NaiveDateTime>,
+    pub family_id: Uuid,
+    refresh_token_hash: Option<String>,
+    device_name: Option<String>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    last_seen_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = password_reset_tokens)]
+pub struct NewPasswordResetToken {
+    token_id: Uuid,
+    user_id: Uuid,
+    token_hash: String,
+    created_at: Option<NaiveDateTime>,
+    expires_at: NaiveDateTime,
 }
 
 #[derive(Insertable)]
-#[table_name="messages"]
+#[diesel(table_name = email_verification_tokens)]
+pub struct NewEmailVerificationToken {
+    token_id: Uuid,
+    user_id: Uuid,
+    email: String,
+    token_hash: String,
+    created_at: Option<NaiveDateTime>,
+    expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = user_recovery_codes)]
+pub struct NewRecoveryCode {
+    code_id: Uuid,
+    user_id: Uuid,
+    code_hash: String,
+    created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = user_identities)]
+pub struct NewUserIdentity {
+    identity_id: Uuid,
+    user_id: Uuid,
+    provider: String,
+    subject: String,
+    email: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    last_login_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = messages)]
 pub struct NewMessage{
-    message_id: Uuid,
-    chat_id: Uuid,
+    pub message_id: Uuid,
+    pub chat_id: Uuid,
     role: String,
     content: String,
-    timestamp: Option<NaiveDateTime>,
+    pub timestamp: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
-#[table_name="chats"]
+#[diesel(table_name = chats)]
 pub struct NewChat{
     pub chat_id: Uuid,
     pub user_id: Uuid,
     pub title: String,
     pub created_at: Option<NaiveDateTime>,
+    pub classroom_id: Option<Uuid>,
+    pub last_message_at: Option<NaiveDateTime>,
+    pub forked_from_chat_id: Option<Uuid>,
+    pub forked_from_message_id: Option<Uuid>,
 }
 
 
 impl NewUser {
-    pub fn new(username: &String, email: &String, password: &String) -> Self {
-        let hashed_password = hash_password(&password).unwrap();
+    pub fn new(username: &str, email: &str, password: &str) -> Self {
+        let hashed_password = hash_password(password).unwrap();
         Self {
             user_id: generate_uuid(),
-            username: username.clone(),
-            email: email.clone(),
+            username: username.to_string(),
+            email: email.to_string(),
             password_hash: hashed_password,
-            created_at: Some(now())
+            created_at: Some(now()),
+            email_verified_at: None,
+        }
+    }
+
+    /// 第三方身份提供方已确认邮箱归属时直接标记为已验证
+    pub fn with_verified_email(mut self) -> Self {
+        self.email_verified_at = Some(now());
+        self
+    }
+}
+
+impl ApiKey {
+    /// 生成新的 API 密钥，返回记录与只展示一次的完整密钥 `mrk_<前缀>_<密钥>`
+    pub fn generate(userid: Uuid, name: &str, scopes: &[String], expires_at: Option<NaiveDateTime>) -> (Self, String) {
+        let key_prefix: String = generate_token()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .take(8)
+            .collect::<String>()
+            .to_lowercase();
+        let full_key = format!("{}{}_{}", API_KEY_PREFIX, key_prefix, generate_token());
+
+        let api_key = Self {
+            api_key_id: generate_uuid(),
+            user_id: userid,
+            name: name.to_string(),
+            key_prefix,
+            key_hash: hash_token(&full_key),
+            scopes: scopes.join(" "),
+            created_at: Some(now()),
+            last_used_at: None,
+            expires_at,
+            revoked_at: None,
+        };
+        (api_key, full_key)
+    }
+
+    /// 从完整密钥中解析出前缀，格式不对时返回 None
+    pub fn parse_prefix(full_key: &str) -> Option<&str> {
+        let rest = full_key.strip_prefix(API_KEY_PREFIX)?;
+        let (prefix, secret) = rest.split_once('_')?;
+        (!prefix.is_empty() && !secret.is_empty()).then_some(prefix)
+    }
+
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes.split_whitespace().map(|s| s.to_string()).collect()
+    }
+}
+
+impl LoginThrottle {
+    // throttle_key 列最长 200 个字符，超长的用户名截断后计数，不能让写入失败绕过计数
+    pub fn user_key(username: &str) -> String {
+        format!("user:{}", username.to_lowercase()).chars().take(200).collect()
+    }
+
+    pub fn ip_key(ip_address: &str) -> String {
+        format!("ip:{}", ip_address).chars().take(200).collect()
+    }
+
+    /// 记录一次失败：超过统计窗口的旧失败不再累计，达到阈值后锁定一段时间
+    pub fn record_failure(previous: Option<LoginThrottle>, key: &str, lockout_threshold: i32, failed_time: NaiveDateTime) -> Self {
+        let window_start = failed_time - Duration::minutes(LOGIN_FAILURE_WINDOW_MINUTES);
+        let previous_count = previous
+            .filter(|throttle| throttle.last_failed_at.is_some_and(|t| t > window_start))
+            .map(|throttle| throttle.failed_count)
+            .unwrap_or(0);
+
+        let failed_count = previous_count + 1;
+        let locked_until = if failed_count >= lockout_threshold {
+            Some(failed_time + Duration::minutes(LOGIN_LOCKOUT_MINUTES))
+        } else {
+            None
+        };
+
+        Self {
+            throttle_key: key.to_string(),
+            failed_count,
+            last_failed_at: Some(failed_time),
+            locked_until,
         }
     }
+
+    /// 距离允许下一次尝试还需等待的秒数。连续失败超过免等待次数后按 2 的幂次退避，锁定期内返回剩余锁定时间
+    pub fn retry_after(&self, current: NaiveDateTime) -> Option<i64> {
+        if let Some(locked_until) = self.locked_until {
+            if locked_until > current {
+                return Some((locked_until - current).num_seconds().max(1));
+            }
+        }
+
+        let last_failed_at = self.last_failed_at?;
+        if self.failed_count <= LOGIN_FREE_ATTEMPTS {
+            return None;
+        }
+
+        let exponent = (self.failed_count - LOGIN_FREE_ATTEMPTS - 1).min(16) as u32;
+        let backoff = (1i64 << exponent).min(LOGIN_MAX_BACKOFF_SECONDS);
+        let wait = backoff - (current - last_failed_at).num_seconds();
+        (wait > 0).then_some(wait)
+    }
 }
 
 impl NewSession{
-    pub fn new(sessionid: Uuid, userid: Uuid, token: &String) -> Self {
+    /// `expires_at` 是刷新令牌的有效期，访问令牌本身的有效期由 JWT 的 `exp` 控制
+    pub fn new(sessionid: Uuid, userid: Uuid, token: &str, familyid: Uuid, refresh_hash: &str) -> Self {
         Self{
             session_id: sessionid,
             user_id: userid,
             token: token.to_string(),
             created_at: Some(now()),
-            expires_at: Some(now().checked_add_signed(Duration::days(1)).unwrap())
+            expires_at: Some(now().checked_add_signed(Duration::days(REFRESH_TOKEN_TTL_DAYS)).unwrap()),
+            family_id: familyid,
+            refresh_token_hash: Some(refresh_hash.to_string()),
+            device_name: None,
+            ip_address: None,
+            user_agent: None,
+            last_seen_at: Some(now()),
+        }
+    }
+
+    pub fn with_client_info(mut self, client: &ClientInfo) -> Self {
+        self.device_name = client.device_name.clone();
+        self.ip_address = client.ip_address.clone();
+        self.user_agent = client.user_agent.clone();
+        self
+    }
+}
+
+/// 登录时记录的客户端信息，用于会话管理页面展示
+pub struct ClientInfo {
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl NewPasswordResetToken {
+    pub fn new(userid: Uuid, token_hash: &str) -> Self {
+        Self {
+            token_id: generate_uuid(),
+            user_id: userid,
+            token_hash: token_hash.to_string(),
+            created_at: Some(now()),
+            expires_at: now().checked_add_signed(Duration::minutes(PASSWORD_RESET_TTL_MINUTES)).unwrap(),
+        }
+    }
+}
+
+impl NewEmailVerificationToken {
+    pub fn new(userid: Uuid, email: &str, token_hash: &str) -> Self {
+        Self {
+            token_id: generate_uuid(),
+            user_id: userid,
+            email: email.to_string(),
+            token_hash: token_hash.to_string(),
+            created_at: Some(now()),
+            expires_at: now().checked_add_signed(Duration::hours(EMAIL_VERIFICATION_TTL_HOURS)).unwrap(),
+        }
+    }
+}
+
+impl NewRecoveryCode {
+    pub fn new(userid: Uuid, code_hash: &str) -> Self {
+        Self {
+            code_id: generate_uuid(),
+            user_id: userid,
+            code_hash: code_hash.to_string(),
+            created_at: Some(now()),
+        }
+    }
+}
+
+impl OidcLoginState {
+    pub fn new(state: &str, provider: &str, code_verifier: &str, nonce: &str) -> Self {
+        Self {
+            state: state.to_string(),
+            provider: provider.to_string(),
+            code_verifier: code_verifier.to_string(),
+            nonce: nonce.to_string(),
+            created_at: Some(now()),
+            expires_at: now().checked_add_signed(Duration::minutes(OIDC_STATE_TTL_MINUTES)).unwrap(),
+        }
+    }
+}
+
+impl Classroom {
+    pub fn new(teacher_id: Uuid, name: &str, system_prompt: Option<String>) -> Self {
+        Self {
+            classroom_id: generate_uuid(),
+            teacher_id,
+            name: name.to_string(),
+            invite_code: generate_invite_code(),
+            system_prompt,
+            created_at: Some(now()),
+        }
+    }
+}
+
+impl Folder {
+    pub fn new(userid: Uuid, parent_id: Option<Uuid>, name: &str) -> Self {
+        Self {
+            folder_id: generate_uuid(),
+            user_id: userid,
+            parent_id,
+            name: name.to_string(),
+            created_at: now(),
+        }
+    }
+}
+
+impl Tag {
+    pub fn new(userid: Uuid, name: &str) -> Self {
+        Self {
+            tag_id: generate_uuid(),
+            user_id: userid,
+            name: name.to_string(),
+            created_at: now(),
+        }
+    }
+}
+
+impl AuditEvent {
+    pub fn new(event_type: &str, actor_id: Option<Uuid>) -> Self {
+        Self {
+            event_id: generate_uuid(),
+            actor_id,
+            event_type: event_type.to_string(),
+            target_type: None,
+            target_id: None,
+            ip_address: None,
+            user_agent: None,
+            details: serde_json::json!({}),
+            created_at: now(),
+        }
+    }
+
+    pub fn with_target(mut self, target_type: &str, target_id: &str) -> Self {
+        self.target_type = Some(target_type.to_string());
+        self.target_id = Some(target_id.chars().take(255).collect());
+        self
+    }
+
+    /// 只保存能解析为 IP 的地址，其余存为空，超长的值不能让审计记录写入失败
+    pub fn with_client_info(mut self, client: &ClientInfo) -> Self {
+        self.ip_address = client.ip_address.as_deref()
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .map(|ip| ip.to_string());
+        self.user_agent = client.user_agent.clone();
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+impl OcrRecord {
+    pub fn new(userid: Uuid, content: &str) -> Self {
+        Self {
+            record_id: generate_uuid(),
+            user_id: userid,
+            content: content.to_string(),
+            created_at: Some(now()),
+        }
+    }
+}
+
+impl Assignment {
+    pub fn new(classroom_id: Uuid, title: &str, problems: &str, problem_latex: Option<String>, due_at: Option<NaiveDateTime>) -> Self {
+        Self {
+            assignment_id: generate_uuid(),
+            classroom_id,
+            title: title.to_string(),
+            problems: problems.to_string(),
+            problem_latex,
+            due_at,
+            created_at: Some(now()),
+        }
+    }
+
+    /// 作为对话第一条消息的题目内容，OCR 识别结果附在文字题目之后
+    pub fn seed_prompt(&self) -> String {
+        match &self.problem_latex {
+            Some(latex) => format!("{}\n\n{}", self.problems, latex),
+            None => self.problems.clone(),
+        }
+    }
+}
+
+impl AssignmentSubmission {
+    pub fn new(assignment_id: Uuid, user_id: Uuid, chat_id: Uuid) -> Self {
+        Self {
+            assignment_id,
+            user_id,
+            chat_id,
+            status: SUBMISSION_IN_PROGRESS.to_string(),
+            started_at: Some(now()),
+            submitted_at: None,
+            reviewed_at: None,
+            feedback: None,
+        }
+    }
+}
+
+impl ClassroomMember {
+    pub fn new(classroom_id: Uuid, user_id: Uuid, share_chats: bool) -> Self {
+        Self {
+            classroom_id,
+            user_id,
+            share_chats,
+            joined_at: Some(now()),
+        }
+    }
+}
+
+impl NewUserIdentity {
+    pub fn new(userid: Uuid, provider: &str, subject: &str, email: Option<&str>) -> Self {
+        Self {
+            identity_id: generate_uuid(),
+            user_id: userid,
+            provider: provider.to_string(),
+            subject: subject.to_string(),
+            email: email.map(|e| e.to_string()),
+            created_at: Some(now()),
+            last_login_at: Some(now()),
         }
     }
 }
@@ -127,9 +741,24 @@ impl NewChat{
             chat_id: generate_uuid(),
             user_id: userid,
             title: title.to_string(),
-            created_at: Some(now())
+            created_at: Some(now()),
+            classroom_id: None,
+            last_message_at: Some(now()),
+            forked_from_chat_id: None,
+            forked_from_message_id: None,
         }
     }
+
+    pub fn with_classroom(mut self, classroom_id: Option<Uuid>) -> Self {
+        self.classroom_id = classroom_id;
+        self
+    }
+
+    pub fn forked_from(mut self, chat_id: Uuid, message_id: Uuid) -> Self {
+        self.forked_from_chat_id = Some(chat_id);
+        self.forked_from_message_id = Some(message_id);
+        self
+    }
 }
 
 
@@ -137,6 +766,129 @@ impl NewChat{
 pub struct LoginPayload {
     pub username: String,
     pub password: String,
+    pub device_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshPayload {
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeSessionsQuery {
+    #[serde(default)]
+    pub include_current: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordPayload {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordPayload {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordPayload {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailPayload {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodePayload {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpDisablePayload {
+    pub password: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+/// 注销账号前重新认证，开启两步验证的账号还需要 `code` 或 `recovery_code`
+#[derive(Deserialize)]
+pub struct DeleteAccountPayload {
+    pub password: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RestoreAccountPayload {
+    pub username: String,
+    pub password: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+/// 两步登录的第二步，`code` 与 `recovery_code` 任选其一
+#[derive(Deserialize)]
+pub struct TotpLoginPayload {
+    pub challenge_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+    pub device_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackPayload {
+    pub code: String,
+    pub state: String,
+    pub device_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKeyPayload {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct UsageStats {
+    pub chat_count: i64,
+    pub message_count: i64,
+    pub recent_message_count: i64,
+    pub active_session_count: i64,
+}
+
+#[derive(Deserialize)]
+pub struct AdminUserListQuery {
+    pub q: Option<String>,
+    pub role: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// 时间范围为左闭右开。结果按时间倒序，翻页时把上一页最后一条的时间作为 `to`；
+/// `event_type` 以 `.*` 结尾时按前缀匹配，例如 `auth.*`
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub user_id: Option<String>,
+    pub event_type: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct AdminRolePayload {
+    pub role: String,
+}
+
+/// 填写 `new_password` 时直接设置新密码，否则向用户邮箱发送重置链接
+#[derive(Deserialize)]
+pub struct AdminPasswordResetPayload {
+    pub new_password: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -149,12 +901,235 @@ pub struct RegisterPayload {
 #[derive(Deserialize)]
 pub struct NewChatPayload {
     pub title: String,
+    pub classroom_id: Option<String>,
+}
+
+/// 对话列表的排序字段，均为倒序
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatSort {
+    #[default]
+    Created,
+    Activity,
+}
+
+impl ChatSort {
+    fn tag(self) -> &'static str {
+        match self {
+            ChatSort::Created => "c",
+            ChatSort::Activity => "a",
+        }
+    }
+}
+
+/// `before` 取游标之后更早的一页，`after` 取游标之前更新的一页，两者不能同时使用。
+/// 返回的 `next_cursor` 沿本次请求的方向继续翻页，没有更多数据时为 null。
+/// 置顶的对话排在前面；默认不含已归档的对话，`archived=true` 只看归档，`pinned` 按置顶筛选。
+/// `folder_id` 只看该文件夹下的对话（不含子文件夹），传空字符串只看不在任何文件夹中的对话；
+/// `tag_id` 只看带有该标签的对话
+#[derive(Deserialize)]
+pub struct ChatHistoryQuery {
+    pub limit: Option<i64>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    #[serde(default)]
+    pub sort: ChatSort,
+    #[serde(default)]
+    pub archived: bool,
+    pub pinned: Option<bool>,
+    pub folder_id: Option<String>,
+    pub tag_id: Option<String>,
+}
+
+/// 对话列表的筛选条件，`folder_id` 为 `Some(None)` 表示顶层
+pub struct ChatListFilter {
+    pub archived: bool,
+    pub pinned: Option<bool>,
+    pub folder_id: Option<Option<Uuid>>,
+    pub tag_id: Option<Uuid>,
+}
+
+/// 未提供的字段保持不变，`folder_id` 传空字符串表示移到顶层
+#[derive(Deserialize)]
+pub struct UpdateChatPayload {
+    pub title: Option<String>,
+    pub pinned: Option<bool>,
+    pub archived: Option<bool>,
+    pub folder_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EditMessagePayload {
+    pub prompt: String,
+}
+
+/// 未提供标题时沿用来源对话的标题
+#[derive(Deserialize)]
+pub struct ForkChatPayload {
+    pub title: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewFolderPayload {
+    pub name: String,
+    pub parent_id: Option<String>,
+}
+
+/// 未提供的字段保持不变，`parent_id` 传空字符串表示移到顶层
+#[derive(Deserialize)]
+pub struct UpdateFolderPayload {
+    pub name: Option<String>,
+    pub parent_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TagPayload {
+    pub name: String,
+}
+
+/// `folder_id` 为空或空字符串时移到顶层
+#[derive(Deserialize)]
+pub struct BulkMovePayload {
+    pub chat_ids: Vec<String>,
+    pub folder_id: Option<String>,
+}
+
+/// 按标签名添加和移除，添加时不存在的标签会自动创建
+#[derive(Deserialize)]
+pub struct BulkTagPayload {
+    pub chat_ids: Vec<String>,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TrashQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// 多个关键词用空格分隔，需全部命中；结果按相关度排序，用 `offset` 翻页
+#[derive(Deserialize)]
+pub struct ChatSearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// 从最新的消息向前翻页：不带 `before_seq` 时返回最新的 `limit` 条，
+/// 之后把返回的 `next_before_seq` 作为 `before_seq` 继续加载更早的消息
+#[derive(Deserialize)]
+pub struct MessagePageQuery {
+    pub limit: Option<i64>,
+    pub before_seq: Option<i64>,
 }
 
+/// 翻页游标：置顶标记、排序字段的值加上 chat_id，保证同一时间的对话也有确定的顺序。
+/// 对外是不透明的 base64url 字符串，并带上排序方式，防止与其他排序混用
+#[derive(Clone, Copy)]
+pub struct ChatCursor {
+    pub sort: ChatSort,
+    pub pinned: bool,
+    pub at: NaiveDateTime,
+    pub chat_id: Uuid,
+}
+
+impl ChatCursor {
+    pub fn from_chat(chat: &Chat, sort: ChatSort) -> Self {
+        let at = match sort {
+            ChatSort::Created => chat.created_at,
+            ChatSort::Activity => chat.last_message_at,
+        };
+        Self { sort, pinned: chat.pinned, at, chat_id: chat.chat_id }
+    }
+
+    pub fn encode(&self) -> String {
+        let raw = format!("{}:{}:{}:{}", self.sort.tag(), u8::from(self.pinned), self.at.and_utc().timestamp_micros(), self.chat_id);
+        general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let raw = String::from_utf8(general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let mut parts = raw.splitn(4, ':');
+        let sort = match parts.next()? {
+            "c" => ChatSort::Created,
+            "a" => ChatSort::Activity,
+            _ => return None,
+        };
+        let pinned = match parts.next()? {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        let at = chrono::DateTime::from_timestamp_micros(parts.next()?.parse().ok()?)?.naive_utc();
+        let chat_id = Uuid::parse_str(parts.next()?).ok()?;
+        Some(Self { sort, pinned, at, chat_id })
+    }
+}
+
+/// 原样转发给模型服务，`system_prompt` 和 `history` 只由服务端填写
 #[derive(Deserialize, Serialize)]
 pub struct ChatPayload {
     pub prompt: String,
     pub chat_id: String,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<HistoryMessage>,
+}
+
+/// 当前分支上此前的消息，随请求一起转发给模型服务
+#[derive(Serialize)]
+pub struct HistoryMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewClassroomPayload {
+    pub name: String,
+    pub system_prompt: Option<String>,
+}
+
+/// 未提供的字段保持不变，`system_prompt` 传空字符串表示清空
+#[derive(Deserialize)]
+pub struct UpdateClassroomPayload {
+    pub name: Option<String>,
+    pub system_prompt: Option<String>,
+}
+
+/// `imgb64` 为可选的题目图片，创建时识别为 LaTeX 保存
+#[derive(Deserialize)]
+pub struct NewAssignmentPayload {
+    pub classroom_id: String,
+    pub title: String,
+    pub problems: String,
+    pub imgb64: Option<String>,
+    pub due_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize)]
+pub struct AssignmentListQuery {
+    pub classroom_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReviewSubmissionPayload {
+    pub feedback: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct JoinClassroomPayload {
+    pub invite_code: String,
+    #[serde(default)]
+    pub share_chats: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ClassroomConsentPayload {
+    pub share_chats: bool,
 }
 
 
@@ -0,0 +1,52 @@
+use std::str::FromStr;
+
+/// 用户角色，与 `users.role` 的取值一一对应
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Admin,
+    Teacher,
+    Student,
+}
+
+/// 需要授权的操作。接口只检查权限，角色与权限的对应关系集中在 `Role::permissions`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Permission {
+    ManageUsers,
+    ManageClassrooms,
+    ViewAuditLog,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Teacher => "teacher",
+            Role::Student => "student",
+        }
+    }
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Admin => &[Permission::ManageUsers, Permission::ManageClassrooms, Permission::ViewAuditLog],
+            Role::Teacher => &[Permission::ManageClassrooms],
+            Role::Student => &[],
+        }
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "teacher" => Ok(Role::Teacher),
+            "student" => Ok(Role::Student),
+            other => Err(format!("未知的角色: {}", other)),
+        }
+    }
+}
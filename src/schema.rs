@@ -1,15 +1,150 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (api_key_id) {
+        api_key_id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 16]
+        key_prefix -> Varchar,
+        key_hash -> Text,
+        scopes -> Text,
+        created_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        expires_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    assignment_submissions (assignment_id, user_id) {
+        assignment_id -> Uuid,
+        user_id -> Uuid,
+        chat_id -> Uuid,
+        #[max_length = 20]
+        status -> Varchar,
+        started_at -> Nullable<Timestamp>,
+        submitted_at -> Nullable<Timestamp>,
+        reviewed_at -> Nullable<Timestamp>,
+        feedback -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    assignments (assignment_id) {
+        assignment_id -> Uuid,
+        classroom_id -> Uuid,
+        #[max_length = 100]
+        title -> Varchar,
+        problems -> Text,
+        problem_latex -> Nullable<Text>,
+        due_at -> Nullable<Timestamp>,
+        created_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_events (event_id) {
+        event_id -> Uuid,
+        actor_id -> Nullable<Uuid>,
+        #[max_length = 50]
+        event_type -> Varchar,
+        #[max_length = 50]
+        target_type -> Nullable<Varchar>,
+        #[max_length = 255]
+        target_id -> Nullable<Varchar>,
+        #[max_length = 45]
+        ip_address -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        details -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    chat_tags (chat_id, tag_id) {
+        chat_id -> Uuid,
+        tag_id -> Uuid,
+    }
+}
+
 diesel::table! {
     chats (chat_id) {
         chat_id -> Uuid,
         user_id -> Uuid,
         #[max_length = 100]
         title -> Varchar,
+        created_at -> Timestamp,
+        classroom_id -> Nullable<Uuid>,
+        last_message_at -> Timestamp,
+        last_message_seq -> Int8,
+        pinned -> Bool,
+        archived -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+        folder_id -> Nullable<Uuid>,
+        active_message_id -> Nullable<Uuid>,
+        forked_from_chat_id -> Nullable<Uuid>,
+        forked_from_message_id -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    classroom_members (classroom_id, user_id) {
+        classroom_id -> Uuid,
+        user_id -> Uuid,
+        share_chats -> Bool,
+        joined_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    classrooms (classroom_id) {
+        classroom_id -> Uuid,
+        teacher_id -> Uuid,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 16]
+        invite_code -> Varchar,
+        system_prompt -> Nullable<Text>,
         created_at -> Nullable<Timestamp>,
     }
 }
 
+diesel::table! {
+    email_verification_tokens (token_id) {
+        token_id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 100]
+        email -> Varchar,
+        token_hash -> Text,
+        created_at -> Nullable<Timestamp>,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    folders (folder_id) {
+        folder_id -> Uuid,
+        user_id -> Uuid,
+        parent_id -> Nullable<Uuid>,
+        #[max_length = 100]
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_throttles (throttle_key) {
+        #[max_length = 200]
+        throttle_key -> Varchar,
+        failed_count -> Int4,
+        last_failed_at -> Nullable<Timestamp>,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     messages (message_id) {
         message_id -> Uuid,
@@ -18,6 +153,83 @@ diesel::table! {
         role -> Varchar,
         content -> Text,
         timestamp -> Nullable<Timestamp>,
+        seq -> Int8,
+        parent_message_id -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    ocr_records (record_id) {
+        record_id -> Uuid,
+        user_id -> Uuid,
+        content -> Text,
+        created_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    oidc_login_states (state) {
+        #[max_length = 128]
+        state -> Varchar,
+        #[max_length = 50]
+        provider -> Varchar,
+        code_verifier -> Text,
+        nonce -> Text,
+        created_at -> Nullable<Timestamp>,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (token_id) {
+        token_id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Text,
+        created_at -> Nullable<Timestamp>,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    tags (tag_id) {
+        tag_id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 50]
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    used_mfa_challenges (jti) {
+        jti -> Uuid,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_identities (identity_id) {
+        identity_id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 50]
+        provider -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        #[max_length = 100]
+        email -> Nullable<Varchar>,
+        created_at -> Nullable<Timestamp>,
+        last_login_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_recovery_codes (code_id) {
+        code_id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Text,
+        created_at -> Nullable<Timestamp>,
+        used_at -> Nullable<Timestamp>,
     }
 }
 
@@ -28,6 +240,15 @@ diesel::table! {
         token -> Text,
         created_at -> Nullable<Timestamp>,
         expires_at -> Timestamp,
+        family_id -> Uuid,
+        refresh_token_hash -> Nullable<Text>,
+        rotated_at -> Nullable<Timestamp>,
+        #[max_length = 100]
+        device_name -> Nullable<Varchar>,
+        #[max_length = 45]
+        ip_address -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        last_seen_at -> Nullable<Timestamp>,
     }
 }
 
@@ -40,16 +261,60 @@ diesel::table! {
         email -> Varchar,
         password_hash -> Text,
         created_at -> Nullable<Timestamp>,
+        email_verified_at -> Nullable<Timestamp>,
+        totp_secret -> Nullable<Text>,
+        totp_enabled_at -> Nullable<Timestamp>,
+        totp_last_used_step -> Nullable<Int8>,
+        #[max_length = 20]
+        role -> Varchar,
+        disabled_at -> Nullable<Timestamp>,
+        deletion_scheduled_at -> Nullable<Timestamp>,
     }
 }
 
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(assignment_submissions -> assignments (assignment_id));
+diesel::joinable!(assignment_submissions -> chats (chat_id));
+diesel::joinable!(assignment_submissions -> users (user_id));
+diesel::joinable!(assignments -> classrooms (classroom_id));
+diesel::joinable!(chat_tags -> chats (chat_id));
+diesel::joinable!(chat_tags -> tags (tag_id));
+diesel::joinable!(chats -> classrooms (classroom_id));
+diesel::joinable!(chats -> folders (folder_id));
 diesel::joinable!(chats -> users (user_id));
+diesel::joinable!(classroom_members -> classrooms (classroom_id));
+diesel::joinable!(classroom_members -> users (user_id));
+diesel::joinable!(classrooms -> users (teacher_id));
+diesel::joinable!(email_verification_tokens -> users (user_id));
+diesel::joinable!(folders -> users (user_id));
 diesel::joinable!(messages -> chats (chat_id));
+diesel::joinable!(ocr_records -> users (user_id));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(tags -> users (user_id));
+diesel::joinable!(user_identities -> users (user_id));
+diesel::joinable!(user_recovery_codes -> users (user_id));
 diesel::joinable!(user_sessions -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    assignment_submissions,
+    assignments,
+    audit_events,
+    chat_tags,
     chats,
+    classroom_members,
+    classrooms,
+    email_verification_tokens,
+    folders,
+    login_throttles,
     messages,
+    ocr_records,
+    oidc_login_states,
+    password_reset_tokens,
+    tags,
+    used_mfa_challenges,
+    user_identities,
+    user_recovery_codes,
     user_sessions,
     users,
 );
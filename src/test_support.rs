@@ -0,0 +1,66 @@
+//! 测试用的数据库连接与数据构造。需要数据库的测试标记为 `#[ignore]`，读取 `TEST_DATABASE_URL`
+//! 指向已执行全部迁移的 Postgres，通过 `cargo test -- --include-ignored` 运行
+use std::env;
+use std::sync::OnceLock;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use uuid::Uuid;
+use crate::database::{add_new_chat, add_new_message, DbPool};
+use crate::models::{NewChat, NewMessage};
+use crate::schema::users;
+use crate::utils::generate_uuid;
+
+/// 未配置 JWT 密钥时使用固定的测试密钥，需在首次签发或校验令牌之前调用
+pub fn init_jwt_env() {
+    if env::var("JWT_SECRET").is_err() && env::var("JWT_KEYS").is_err() {
+        env::set_var("JWT_SECRET", "test-secret-for-cargo-test-only");
+    }
+}
+
+pub fn test_pool() -> DbPool {
+    static POOL: OnceLock<DbPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let database_url = env::var("TEST_DATABASE_URL").expect("需要数据库的测试必须设置 TEST_DATABASE_URL");
+        init_jwt_env();
+        let manager = ConnectionManager::<PgConnection>::new(database_url);
+        Pool::builder().max_size(4).build(manager).expect("测试数据库连接失败")
+    }).clone()
+}
+
+/// 直接写入用户，不走 bcrypt，测试不需要能用密码登录
+pub fn create_user(pool: &DbPool) -> Uuid {
+    let user_id = generate_uuid();
+    let name = format!("t_{}", user_id.simple());
+    let mut conn = pool.get().unwrap();
+
+    diesel::insert_into(users::table)
+        .values((
+            users::user_id.eq(user_id),
+            users::username.eq(&name[..30]),
+            users::email.eq(format!("{}@example.com", &name[..30])),
+            users::password_hash.eq("!"),
+        ))
+        .execute(&mut conn)
+        .unwrap();
+
+    user_id
+}
+
+/// 对话、消息等随用户级联删除
+pub fn delete_user(pool: &DbPool, user_id: Uuid) {
+    let mut conn = pool.get().unwrap();
+    diesel::delete(users::table.filter(users::user_id.eq(user_id)))
+        .execute(&mut conn)
+        .unwrap();
+}
+
+/// 创建带一条用户消息的对话
+pub fn create_chat(pool: &DbPool, user_id: Uuid) -> Uuid {
+    let new_chat = NewChat::new(user_id, &String::from("测试对话"));
+    add_new_chat(pool, &new_chat).unwrap();
+
+    let message = NewMessage::new(new_chat.chat_id, &String::from("user"), &String::from("求 x^2 的导数"));
+    add_new_message(pool, &message).unwrap();
+
+    new_chat.chat_id
+}
@@ -1,44 +1,369 @@
 use bcrypt::{hash, DEFAULT_COST};
-use chrono::{Local, NaiveDateTime};
+use chrono::{Local, NaiveDateTime, Utc};
 use uuid::Uuid;
-use jsonwebtoken::{encode, Header, EncodingKey, decode, DecodingKey, Validation, errors::Error, Algorithm};
-use serde::{Serialize, Deserialize};
+use jsonwebtoken::{encode, Header, EncodingKey, decode, decode_header, DecodingKey, Validation, errors::Error, errors::ErrorKind, Algorithm};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 use dotenv::dotenv;
-use std::{collections::HashSet, env};
+use std::{collections::HashMap, env, fs, net::IpAddr, sync::OnceLock};
+use rand::{Rng, RngCore};
+use sha2::{Sha256, Digest};
+use base64::{engine::general_purpose, Engine as _};
+
+/// 访问令牌的有效期（秒），过期后需要用刷新令牌换取新的访问令牌
+pub const ACCESS_TOKEN_TTL_SECONDS: i64 = 15 * 60;
+
+/// 刷新令牌的有效期（天），每次刷新都会顺延
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+/// 两步登录挑战令牌的有效期（秒）
+pub const MFA_CHALLENGE_TTL_SECONDS: i64 = 5 * 60;
+
+/// 密码重置令牌的有效期（分钟）
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+
+/// 邮箱验证令牌的有效期（小时）
+pub const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+
+/// OIDC 授权请求从发起到回调的最长间隔（分钟）
+pub const OIDC_STATE_TTL_MINUTES: i64 = 10;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// 申请注销后的宽限期，期间可以恢复账号
+pub const ACCOUNT_DELETION_GRACE_DAYS: i64 = 14;
+
+/// 回收站中的对话默认保留天数，可通过 `CHAT_TRASH_RETENTION_DAYS` 配置
+const DEFAULT_CHAT_TRASH_RETENTION_DAYS: i64 = 30;
+/// 保留天数上限，过大的配置会让日期计算溢出
+const MAX_CHAT_TRASH_RETENTION_DAYS: i64 = 3650;
+
+pub fn chat_trash_retention_days() -> i64 {
+    env::var("CHAT_TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|days| *days > 0)
+        .unwrap_or(DEFAULT_CHAT_TRASH_RETENTION_DAYS)
+        .min(MAX_CHAT_TRASH_RETENTION_DAYS)
+}
+
+/// API 密钥的固定前缀，便于在日志和代码仓库中识别泄露的密钥
+pub const API_KEY_PREFIX: &str = "mrk_";
+
+/// API 密钥可申请的权限范围
+pub const SCOPE_CHAT_READ: &str = "chat:read";
+pub const SCOPE_CHAT_WRITE: &str = "chat:write";
+pub const SCOPE_OCR: &str = "ocr";
+pub const API_KEY_SCOPES: &[&str] = &[SCOPE_CHAT_READ, SCOPE_CHAT_WRITE, SCOPE_OCR];
+
+/// 作业提交状态，与 `assignment_submissions.status` 的取值一致
+pub const SUBMISSION_IN_PROGRESS: &str = "in_progress";
+pub const SUBMISSION_SUBMITTED: &str = "submitted";
+pub const SUBMISSION_REVIEWED: &str = "reviewed";
+
+/// 登录失败的统计窗口（分钟），窗口外的失败不再累计
+pub const LOGIN_FAILURE_WINDOW_MINUTES: i64 = 60;
+
+/// 连续失败多少次以内不需要等待
+pub const LOGIN_FREE_ATTEMPTS: i32 = 3;
+
+/// 指数退避的最长等待时间（秒）
+pub const LOGIN_MAX_BACKOFF_SECONDS: i64 = 5 * 60;
+
+/// 同一用户名、同一 IP 的锁定阈值。IP 可能被多人共用（如校园网出口），阈值放宽
+pub const LOGIN_USER_LOCKOUT_THRESHOLD: i32 = 10;
+pub const LOGIN_IP_LOCKOUT_THRESHOLD: i32 = 50;
+
+/// 达到阈值后的锁定时长（分钟）
+pub const LOGIN_LOCKOUT_MINUTES: i64 = 15;
 
 #[derive(Serialize, Deserialize)]
-struct Claims {
-    session_id: String,
+pub struct Claims {
+    pub sub: String,
+    pub session_id: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+    pub iss: String,
+    pub aud: String,
+}
+
+/// 两步登录中密码校验通过后签发的挑战令牌，只用于换取 TOTP 登录
+#[derive(Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+    pub iss: String,
+    pub aud: String,
+}
+
+impl Claims {
+    pub fn session_uuid(&self) -> Result<Uuid, Error> {
+        Uuid::parse_str(&self.session_id).map_err(|_| Error::from(ErrorKind::InvalidToken))
+    }
+
+    pub fn user_uuid(&self) -> Result<Uuid, Error> {
+        Uuid::parse_str(&self.sub).map_err(|_| Error::from(ErrorKind::InvalidToken))
+    }
+}
+
+struct JwtKey {
+    algorithm: Algorithm,
+    encoding_key: Option<EncodingKey>,
+    decoding_key: DecodingKey,
 }
 
-pub fn generate_jwt(session_id: &Uuid) -> Result<String, jsonwebtoken::errors::Error> {
+/// 签发与校验 JWT 所用的密钥集合，按 `kid` 索引以支持密钥轮换。
+///
+/// 通过环境变量 `JWT_KEYS` 配置，多个密钥之间用 `;` 分隔，每个密钥的格式为
+/// `kid,算法,校验密钥[,签名密钥]`：
+/// - `HS256` 的校验密钥即共享密钥本身，无需再填签名密钥；
+/// - `RS256` / `EdDSA` 的校验密钥为公钥 PEM 文件路径，签名密钥为私钥 PEM 文件路径。
+///
+/// `JWT_SIGNING_KID` 指定用于签发新令牌的密钥，只保留校验密钥的旧 `kid` 仍可校验已签发的令牌。
+/// 未配置 `JWT_KEYS` 时退回到 `JWT_SECRET`，作为 `kid` 为 `default` 的 HS256 密钥。
+struct JwtKeySet {
+    keys: HashMap<String, JwtKey>,
+    signing_kid: String,
+    issuer: String,
+    audience: String,
+}
+
+fn read_pem(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|err| panic!("无法读取密钥文件 {}: {}", path, err))
+}
+
+fn parse_jwt_key(entry: &str) -> (String, JwtKey) {
+    let parts: Vec<&str> = entry.split(',').map(|s| s.trim()).collect();
+    if parts.len() < 3 {
+        panic!("JWT_KEYS 格式错误: {}", entry);
+    }
+    let kid = parts[0].to_string();
+    let signing_source = parts.get(3).copied();
+
+    let key = match parts[1] {
+        "HS256" => JwtKey {
+            algorithm: Algorithm::HS256,
+            encoding_key: Some(EncodingKey::from_secret(parts[2].as_bytes())),
+            decoding_key: DecodingKey::from_secret(parts[2].as_bytes()),
+        },
+        "RS256" => JwtKey {
+            algorithm: Algorithm::RS256,
+            encoding_key: signing_source.map(|path| {
+                EncodingKey::from_rsa_pem(&read_pem(path)).expect("RS256 私钥格式错误")
+            }),
+            decoding_key: DecodingKey::from_rsa_pem(&read_pem(parts[2])).expect("RS256 公钥格式错误"),
+        },
+        "EdDSA" => JwtKey {
+            algorithm: Algorithm::EdDSA,
+            encoding_key: signing_source.map(|path| {
+                EncodingKey::from_ed_pem(&read_pem(path)).expect("EdDSA 私钥格式错误")
+            }),
+            decoding_key: DecodingKey::from_ed_pem(&read_pem(parts[2])).expect("EdDSA 公钥格式错误"),
+        },
+        other => panic!("不支持的 JWT 算法: {}", other),
+    };
+
+    (kid, key)
+}
+
+fn load_jwt_key_set() -> JwtKeySet {
+    dotenv().ok();
+
+    let mut keys = HashMap::new();
+    let signing_kid = match env::var("JWT_KEYS") {
+        Ok(entries) => {
+            for entry in entries.split(';').filter(|e| !e.trim().is_empty()) {
+                let (kid, key) = parse_jwt_key(entry);
+                keys.insert(kid, key);
+            }
+            env::var("JWT_SIGNING_KID").expect("JWT_SIGNING_KID must be set")
+        }
+        Err(_) => {
+            let secret_key = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+            keys.insert(String::from("default"), JwtKey {
+                algorithm: Algorithm::HS256,
+                encoding_key: Some(EncodingKey::from_secret(secret_key.as_ref())),
+                decoding_key: DecodingKey::from_secret(secret_key.as_ref()),
+            });
+            String::from("default")
+        }
+    };
+
+    match keys.get(&signing_kid) {
+        Some(key) if key.encoding_key.is_some() => {}
+        _ => panic!("JWT_SIGNING_KID {} 没有可用的签名密钥", signing_kid),
+    }
+
+    JwtKeySet {
+        keys,
+        signing_kid,
+        issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| String::from("math-rag")),
+        audience: env::var("JWT_AUDIENCE").unwrap_or_else(|_| String::from("math-rag-client")),
+    }
+}
+
+fn jwt_key_set() -> &'static JwtKeySet {
+    static KEY_SET: OnceLock<JwtKeySet> = OnceLock::new();
+    KEY_SET.get_or_init(load_jwt_key_set)
+}
+
+fn sign_claims<T: Serialize>(claims: &T) -> Result<String, Error> {
+    let key_set = jwt_key_set();
+    let signing_key = &key_set.keys[&key_set.signing_kid];
+
+    let mut header = Header::new(signing_key.algorithm);
+    header.kid = Some(key_set.signing_kid.clone());
+
+    encode(&header, claims, signing_key.encoding_key.as_ref().unwrap())
+}
+
+/// 按 `kid` 选取密钥，校验签名、签发者、受众与过期时间，任何一项不通过都返回错误
+fn verify_claims<T: DeserializeOwned>(token: &str, audience: &str) -> Result<T, Error> {
+    let key_set = jwt_key_set();
+
+    let header = decode_header(token)?;
+    let key = header.kid
+        .as_ref()
+        .and_then(|kid| key_set.keys.get(kid))
+        .ok_or_else(|| Error::from(ErrorKind::InvalidToken))?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_issuer(&[&key_set.issuer]);
+    validation.set_audience(&[audience]);
+    validation.set_required_spec_claims(&["exp", "iat", "sub", "iss", "aud"]);
+
+    Ok(decode::<T>(token, &key.decoding_key, &validation)?.claims)
+}
+
+/// 挑战令牌使用独立的受众，不能被当作访问令牌使用
+fn challenge_audience() -> String {
+    format!("{}:mfa", jwt_key_set().audience)
+}
+
+pub fn generate_jwt(session_id: &Uuid, user_id: &Uuid) -> Result<String, Error> {
+    let key_set = jwt_key_set();
+
+    let iat = Utc::now().timestamp();
     let claims = Claims {
+        sub: user_id.to_string(),
         session_id: session_id.to_string(),
+        iat,
+        exp: iat + ACCESS_TOKEN_TTL_SECONDS,
+        jti: generate_uuid().to_string(),
+        iss: key_set.issuer.clone(),
+        aud: key_set.audience.clone(),
     };
 
-    dotenv().ok();
-    let secret_key = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    sign_claims(&claims)
+}
 
-    let encoding_key = EncodingKey::from_secret(secret_key.as_ref());
+pub fn decode_jwt(token: &str) -> Result<Claims, Error> {
+    let claims: Claims = verify_claims(token, &jwt_key_set().audience)?;
+    claims.session_uuid()?;
+    claims.user_uuid()?;
 
-    encode(&Header::default(), &claims, &encoding_key)
+    Ok(claims)
 }
 
-pub fn decode_jwt(token: &str) -> Result<Uuid, Error> {
-    dotenv().ok();
-    let secret_key = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+pub fn generate_challenge_jwt(user_id: &Uuid) -> Result<String, Error> {
+    let iat = Utc::now().timestamp();
+    let claims = ChallengeClaims {
+        sub: user_id.to_string(),
+        iat,
+        exp: iat + MFA_CHALLENGE_TTL_SECONDS,
+        jti: generate_uuid().to_string(),
+        iss: jwt_key_set().issuer.clone(),
+        aud: challenge_audience(),
+    };
+
+    sign_claims(&claims)
+}
+
+/// 返回挑战令牌中的用户 ID 与 `jti`，`jti` 用于保证同一挑战令牌只能完成一次登录
+pub fn decode_challenge_jwt(token: &str) -> Result<(Uuid, Uuid), Error> {
+    let claims: ChallengeClaims = verify_claims(token, &challenge_audience())?;
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| Error::from(ErrorKind::InvalidToken))?;
+    let jti = Uuid::parse_str(&claims.jti).map_err(|_| Error::from(ErrorKind::InvalidToken))?;
+
+    Ok((user_id, jti))
+}
+
+/// 从 `Authorization` 头中取出令牌，兼容带或不带 `Bearer ` 前缀的写法
+pub fn extract_token(auth_header: &str) -> &str {
+    auth_header.strip_prefix("Bearer ").unwrap_or(auth_header).trim()
+}
+
+/// 生成 256 位的随机令牌，用于刷新令牌等只需保存哈希的凭据
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// 班级邀请码：8 位大写字母和数字，去掉了容易混淆的 0/O、1/I
+pub fn generate_invite_code() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let mut rng = rand::thread_rng();
+    (0..8)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect()
+}
+
+/// 随机令牌熵足够高，直接保存 SHA-256 即可，不需要 bcrypt
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// 邮箱的语法校验：恰好一个 `@`，域名至少两段且只包含字母、数字和连字符，长度不超过数据库字段
+pub fn is_valid_email(email: &str) -> bool {
+    if email.len() > 100 || email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+
+    let (local, domain) = match email.split_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    if local.is_empty() || local.len() > 64 || local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+        return false;
+    }
+    if !local.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c)) {
+        return false;
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    labels.len() >= 2 && labels.iter().all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
 
-    let decoding_key = DecodingKey::from_secret(secret_key.as_ref());
-    
-    let mut validation = Validation::new(Algorithm::HS256);
-    validation.insecure_disable_signature_validation();
-    validation.validate_exp = false;
-    validation.required_spec_claims = HashSet::new();
+/// 可信的反向代理地址，来自逗号分隔的 `TRUSTED_PROXIES`。只有直连地址是可信代理时才采信 X-Forwarded-For
+pub fn trusted_proxies() -> &'static [IpAddr] {
+    static PROXIES: OnceLock<Vec<IpAddr>> = OnceLock::new();
+    PROXIES.get_or_init(|| {
+        env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect()
+    })
+}
 
-    let decoded = decode::<Claims>(token, &decoding_key, &validation)?;
+/// 读取布尔型配置项，`true`/`1` 视为开启
+pub fn env_flag(name: &str) -> bool {
+    env::var(name).map(|v| v == "true" || v == "1").unwrap_or(false)
+}
 
-    Uuid::parse_str(&decoded.claims.session_id)
-        .map_err(|_| Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken))
+/// 用户不存在时也做一次 bcrypt 校验，使响应时间与密码错误时一致
+pub fn dummy_password_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password(&generate_token()).unwrap())
 }
 
 pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
@@ -51,4 +376,140 @@ pub fn generate_uuid() -> Uuid {
 
 pub fn now() -> NaiveDateTime {
     Local::now().naive_local()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::init_jwt_env;
+
+    fn access_claims(exp: i64) -> Claims {
+        let key_set = jwt_key_set();
+        Claims {
+            sub: generate_uuid().to_string(),
+            session_id: generate_uuid().to_string(),
+            iat: Utc::now().timestamp(),
+            exp,
+            jti: generate_uuid().to_string(),
+            iss: key_set.issuer.clone(),
+            aud: key_set.audience.clone(),
+        }
+    }
+
+    fn error_kind(token: &str) -> ErrorKind {
+        match decode_jwt(token) {
+            Ok(_) => panic!("令牌不应通过校验"),
+            Err(err) => err.into_kind(),
+        }
+    }
+
+    #[test]
+    fn accepts_freshly_issued_token() {
+        init_jwt_env();
+        let session_id = generate_uuid();
+        let user_id = generate_uuid();
+
+        let claims = decode_jwt(&generate_jwt(&session_id, &user_id).unwrap()).unwrap();
+        assert_eq!(claims.session_uuid().unwrap(), session_id);
+        assert_eq!(claims.user_uuid().unwrap(), user_id);
+    }
+
+    #[test]
+    fn rejects_tampered_signature() {
+        init_jwt_env();
+        let token = generate_jwt(&generate_uuid(), &generate_uuid()).unwrap();
+        let other = generate_jwt(&generate_uuid(), &generate_uuid()).unwrap();
+
+        // 用另一个令牌的载荷替换原载荷，签名保持不变
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let (signed, _) = other.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", signed, signature);
+
+        assert!(matches!(error_kind(&forged), ErrorKind::InvalidSignature));
+    }
+
+    #[test]
+    fn rejects_unknown_kid() {
+        init_jwt_env();
+        let key_set = jwt_key_set();
+        let signing_key = &key_set.keys[&key_set.signing_kid];
+
+        let mut header = Header::new(signing_key.algorithm);
+        header.kid = Some(String::from("unknown-kid"));
+        let token = encode(&header, &access_claims(Utc::now().timestamp() + 60), signing_key.encoding_key.as_ref().unwrap()).unwrap();
+
+        assert!(matches!(error_kind(&token), ErrorKind::InvalidToken));
+
+        header.kid = None;
+        let token = encode(&header, &access_claims(Utc::now().timestamp() + 60), signing_key.encoding_key.as_ref().unwrap()).unwrap();
+        assert!(matches!(error_kind(&token), ErrorKind::InvalidToken));
+    }
+
+    #[test]
+    fn rejects_wrong_issuer_and_audience() {
+        init_jwt_env();
+        let mut claims = access_claims(Utc::now().timestamp() + 60);
+        claims.iss = String::from("someone-else");
+        assert!(matches!(error_kind(&sign_claims(&claims).unwrap()), ErrorKind::InvalidIssuer));
+
+        let mut claims = access_claims(Utc::now().timestamp() + 60);
+        claims.aud = String::from("someone-else");
+        assert!(matches!(error_kind(&sign_claims(&claims).unwrap()), ErrorKind::InvalidAudience));
+    }
+
+    #[test]
+    fn challenge_and_access_tokens_are_not_interchangeable() {
+        init_jwt_env();
+        let challenge = generate_challenge_jwt(&generate_uuid()).unwrap();
+        assert!(decode_jwt(&challenge).is_err());
+
+        let access = generate_jwt(&generate_uuid(), &generate_uuid()).unwrap();
+        assert!(decode_challenge_jwt(&access).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        init_jwt_env();
+        let token = sign_claims(&access_claims(Utc::now().timestamp() - 3600)).unwrap();
+        assert!(matches!(error_kind(&token), ErrorKind::ExpiredSignature));
+    }
+
+    #[test]
+    fn accepts_common_emails() {
+        assert!(is_valid_email("alice@example.com"));
+        assert!(is_valid_email("first.last+tag@mail.example.co"));
+        assert!(is_valid_email("a_b-c@sub-domain.example.org"));
+    }
+
+    #[test]
+    fn rejects_malformed_emails() {
+        for email in [
+            "",
+            "alice",
+            "alice@",
+            "@example.com",
+            "alice@@example.com",
+            "alice@example",
+            "alice@example..com",
+            "alice@-example.com",
+            "alice@example-.com",
+            ".alice@example.com",
+            "alice.@example.com",
+            "al..ice@example.com",
+            "al ice@example.com",
+            "alice@exa_mple.com",
+            "<script>@example.com",
+        ] {
+            assert!(!is_valid_email(email), "{:?}", email);
+        }
+    }
+
+    #[test]
+    fn rejects_overlong_emails() {
+        let local = "a".repeat(65);
+        assert!(!is_valid_email(&format!("{}@example.com", local)));
+
+        let domain = format!("{}.com", "a".repeat(95));
+        assert!(!is_valid_email(&format!("alice@{}", domain)));
+    }
+}
@@ -20,7 +20,7 @@ fn assmble_header(body: Value) -> HeaderMap{
     let body_str = body.to_string();
     hasher.update(body_str.as_bytes());
     let hash_result = hasher.finalize();
-    let sha256_body = general_purpose::STANDARD.encode(&hash_result);
+    let sha256_body = general_purpose::STANDARD.encode(hash_result);
     let mut digest = String::from("SHA-256=");
     digest.push_str(&sha256_body);
 